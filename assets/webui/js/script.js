@@ -58,7 +58,7 @@ async function post(action) {
 
 const forms = document.getElementsByTagName("form");
 
-const form_action = ["room", "room/join", "room/exit", "room/create", "room/delete", "stream/whip", "stream/whep", "stream/reforward", "stream/infos", "send_rtc_message", "ws/connect", "send_ws_message"];
+const form_action = ["room", "room/join", "room/exit", "room/create", "room/delete", "room/kick", "room/property/set", "room/roster", "stream/whip", "stream/whep", "stream/reforward", "stream/infos", "send_rtc_message", "ws/connect", "send_ws_message"];
 
 function onTrack(event) {
     const track = event.track;
@@ -93,6 +93,10 @@ window.addEventListener('DOMContentLoaded', () => {
                 const json = {};
                 formData.forEach((value, key) => {
                     elem = form.getElementsByName(key)[0];
+                    if (elem.getAttribute("data-type") == "json") {
+                        json[key] = JSON.parse(value);
+                        return;
+                    }
                     switch (elem.getAttribute("type")) {
                         case "number":
                             json[key] = Number(value);
@@ -152,7 +156,7 @@ window.addEventListener('DOMContentLoaded', () => {
                                             document.getElementById(elem_id).getElementsByName("to")[0].setAttribute("value", response.id);
                                         });
 
-                                        ["room/exit", "stream/whip", "stream/whep", "ws/connect"].forEach((elem_id) => {
+                                        ["room/exit", "room/property/set", "room/roster", "stream/whip", "stream/whep", "ws/connect"].forEach((elem_id) => {
                                             document.getElementById(elem_id).getElementsByName("user_id")[0].setAttribute("value", response.id);
                                             document.getElementById(elem_id).getElementsByName("token")[0].setAttribute("value", response.token);
                                         });
@@ -166,7 +170,7 @@ window.addEventListener('DOMContentLoaded', () => {
                                             document.getElementById(elem_id).getElementsByName("id")[0].setAttribute("value", response.id);
                                         });
 
-                                        ["room/exit", "stream/whip", "stream/whep", "ws/connect"].forEach((elem_id) => {
+                                        ["room/exit", "room/kick", "room/property/set", "room/roster", "stream/whip", "stream/whep", "ws/connect"].forEach((elem_id) => {
                                             document.getElementById(elem_id).getElementsByName("room_id")[0].setAttribute("value", response.id);
                                         });
                                     }
@@ -196,7 +200,7 @@ window.addEventListener('DOMContentLoaded', () => {
             document.getElementById(elem_id).getElementsByName("id")[0].setAttribute("value", room_id);
         });
 
-        ["room/exit", "stream/whip", "stream/whep", "ws/connect"].forEach((elem_id) => {
+        ["room/exit", "room/kick", "room/property/set", "room/roster", "stream/whip", "stream/whep", "ws/connect"].forEach((elem_id) => {
             document.getElementById(elem_id).getElementsByName("room_id")[0].setAttribute("value", room_id);
         });
     }
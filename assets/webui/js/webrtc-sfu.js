@@ -238,6 +238,15 @@ class SfuPeerConnection extends SfuClient {
       case 2:
         console.log('[rtc-sfu] close: ' + this.Uint8ArrayToi32(buf.slice(1, 4)));
         break;
+      case 3:
+        console.log('[rtc-sfu] host changed: ' + this.Uint8ArrayToi32(buf.slice(1, 5)) + ' (previous: ' + this.Uint8ArrayToi32(buf.slice(5, 9)) + ')');
+        break;
+      case 4:
+        console.log('[rtc-sfu] properties: ' + this.buffer_to_string(buf.slice(9)));
+        break;
+      case 5:
+        console.log('[rtc-sfu] roster: ' + this.buffer_to_string(buf.slice(9)));
+        break;
     }
   }
 
@@ -62,6 +62,15 @@ class SfuWebSocket extends SfuClient {
                 case 2:
                     console.log('[ws-sfu] close: ' + this.Uint8ArrayToi32(buf.slice(1, 4)));
                     break;
+                case 3:
+                    console.log('[ws-sfu] host changed: ' + this.Uint8ArrayToi32(buf.slice(1, 5)) + ' (previous: ' + this.Uint8ArrayToi32(buf.slice(5, 9)) + ')');
+                    break;
+                case 4:
+                    console.log('[ws-sfu] properties: ' + this.buffer_to_string(buf.slice(9)));
+                    break;
+                case 5:
+                    console.log('[ws-sfu] roster: ' + this.buffer_to_string(buf.slice(9)));
+                    break;
             }
         });
 
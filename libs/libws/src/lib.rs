@@ -2,7 +2,7 @@
 // See https://github.com/mohammadjavad948/axum-ws-rooms
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Error,
     fmt,
     sync::{
@@ -19,6 +19,27 @@ use tokio::{
 
 use tracing::{debug, warn};
 
+/// First byte of every relayed message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventType {
+    Struct = 0,
+    Open = 1,
+    Close = 2,
+    HostChanged = 3,
+    Properties = 4,
+    Roster = 5,
+}
+
+/// Builds a payload-less event: typ (1) + from (0 ~ 3) + to (4 ~ 7).
+pub fn event_message(typ: EventType, from: u32, to: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(9);
+    buf.push(typ as u8);
+    buf.extend_from_slice(&from.to_le_bytes());
+    buf.extend_from_slice(&to.to_le_bytes());
+    buf
+}
+
 pub struct Group {
     pub name: String,
     tx: broadcast::Sender<Vec<u8>>,
@@ -31,6 +52,8 @@ pub struct Group {
 pub struct GroupsManager {
     inner: Mutex<HashMap<String, Group>>,
     users_group: Mutex<HashMap<u32, Vec<String>>>,
+    /// users whose leaving is not announced with a Close event
+    silent_users: Mutex<HashSet<u32>>,
 }
 
 #[derive(Debug)]
@@ -102,9 +125,9 @@ impl Group {
                         if from == user {
                             continue; // This message was sent from own
                         }
-                        if let Err(_err) = pipe_sender.send(data) {
-                            return;
-                        }
+                        // Fails while the user has no connection attached,
+                        // e.g. between two ws connections, which is fine.
+                        let _ = pipe_sender.send(data);
                     }
                 });
                 v.insert(UserTask { broadcast_pipe });
@@ -113,7 +136,8 @@ impl Group {
         self.tx.clone()
     }
 
-    pub async fn leave(&self, user: u32) {
+    /// `notice` sends a Close event about the user to the rest of the group
+    pub async fn leave(&self, user: u32, notice: bool) {
         let mut inner = self.inner_user.lock().await;
 
         if !inner.contains(&user) {
@@ -137,19 +161,22 @@ impl Group {
             user_senders.remove(&user);
         }
 
-        let mut header = vec![0u8; 5]; // typ (1) + from (0 ~ 3) + to (4 ~ 7)
-        for i in 0..4 {
-            header[i + 1] = (user >> (i * 8)) as u8;
+        if !notice {
+            return;
         }
-
-        header[0] = 2; // close
-        let mut dummy_buf = vec![0u8; 4];
-        for i in 0..4 {
-            dummy_buf[i] = (user >> (i * 8)) as u8;
+        if let Err(err) = self.tx.send(event_message(EventType::Close, user, user)) {
+            debug!("[ws] send socket err: {}", err);
         }
+    }
 
-        if let Err(err) = self.tx.send([header.clone(), dummy_buf.clone()].concat()) {
-            debug!("[ws] send socket err: {}", err);
+    /// Sends to every member, including the one encoded as sender. Used for
+    /// server originated events that everyone must receive.
+    pub fn send_to_all(&self, data: Vec<u8>) {
+        let user_senders = self.user_senders.read().unwrap();
+        for user_sender in user_senders.values() {
+            if let Err(err) = user_sender.send(data.clone()) {
+                debug!("[ws] send socket err: {}", err);
+            }
         }
     }
 
@@ -206,6 +233,7 @@ impl GroupsManager {
         GroupsManager {
             inner: Mutex::new(HashMap::new()),
             users_group: Mutex::new(HashMap::new()),
+            silent_users: Mutex::new(HashSet::new()),
         }
     }
 
@@ -267,6 +295,19 @@ impl GroupsManager {
             .map_err(|_| GroupError::MessageSendFail)
     }
 
+    /// send a message to every user of every group
+    pub async fn send_message_to_all(&self, data: Vec<u8>) {
+        let groups = self.inner.lock().await;
+        for group in groups.values() {
+            group.send_to_all(data.clone());
+        }
+    }
+
+    pub async fn user_groups(&self, user: u32) -> Vec<String> {
+        let users_group = self.users_group.lock().await;
+        users_group.get(&user).cloned().unwrap_or_default()
+    }
+
     pub async fn init_user(&self, user: u32) {
         let mut users_group = self.users_group.lock().await;
         match users_group.entry(user) {
@@ -277,6 +318,12 @@ impl GroupsManager {
         }
     }
 
+    /// mark the user so that leaving a group does not send Close, until end_user
+    pub async fn set_silent(&self, user: u32) {
+        let mut silent_users = self.silent_users.lock().await;
+        silent_users.insert(user);
+    }
+
     pub async fn end_user(&self, user: u32) {
         let mut group_names = Vec::<String>::new();
         let mut users_group = self.users_group.lock().await;
@@ -294,6 +341,9 @@ impl GroupsManager {
                 warn!("end_user filed ! {}", err);
             }
         }
+
+        let mut silent_users = self.silent_users.lock().await;
+        silent_users.remove(&user);
     }
 
     pub async fn join_group(
@@ -347,12 +397,27 @@ impl GroupsManager {
         }
     }
 
+    /// remove every group, e.g. when the owner of this manager is torn down
+    pub async fn clear(&self) {
+        let mut groups = self.inner.lock().await;
+        let mut users_group = self.users_group.lock().await;
+        for group in groups.values() {
+            for user_task in group.user_tasks().await.values() {
+                user_task.broadcast_pipe.abort();
+            }
+        }
+        groups.clear();
+        users_group.clear();
+        self.silent_users.lock().await.clear();
+    }
+
     pub async fn leave_group(&self, name: String, user: u32) -> Result<(), GroupError> {
+        let notice = !self.silent_users.lock().await.contains(&user);
         let groups = self.inner.lock().await;
         groups
             .get(&name)
             .ok_or(GroupError::GroupNotFound)?
-            .leave(user.clone())
+            .leave(user, notice)
             .await;
 
         let mut users_group = self.users_group.lock().await;
@@ -381,6 +446,17 @@ impl GroupsManager {
         groups.len()
     }
 
+    /// every group with its users, ordered by name
+    pub async fn groups(&self) -> Vec<(String, Vec<u32>)> {
+        let groups = self.inner.lock().await;
+        let mut resp = vec![];
+        for group in groups.values() {
+            resp.push((group.name.clone(), group.users().await.clone()));
+        }
+        resp.sort_by(|(a, _), (b, _)| a.cmp(b));
+        resp
+    }
+
     pub async fn get_user_receiver(
         &self,
         group: String,
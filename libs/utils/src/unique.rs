@@ -17,3 +17,22 @@ pub fn generate_unique_u32() -> u32 {
     let mut rng = rand::thread_rng();
     rng.gen::<u32>()
 }
+
+/// Letters and digits that can not be mistaken for each other (no 0/O, 1/I/L).
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+pub fn generate_code(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Opaque session token, 43 alphanumeric characters (~256 bits).
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(43)
+        .map(char::from)
+        .collect()
+}
@@ -1,14 +1,270 @@
-use std::{collections::HashSet, marker::PhantomData};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    marker::PhantomData,
+    str::FromStr,
+    sync::Arc,
+};
 
-use http::{header, Request, Response, StatusCode};
+use anyhow::{anyhow, Context};
+use http::{header, HeaderValue, Request, Response};
 use http_body::Body;
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
 use tower_http::validate_request::ValidateRequest;
+use tracing::debug;
 
-use crate::config::Auth;
+use crate::config::{Auth, AuthScope, Jwt, JwtAlgorithm};
+use crate::error::AppError;
+
+/// What the caller of a request may do, inserted into the request
+/// extensions by `ManyValidate`.
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    pub scope: AuthScope,
+    pub admin: bool,
+    pub create_room: bool,
+    pub join_any_room: bool,
+    pub join_rooms: HashSet<i32>,
+}
+
+impl Permissions {
+    /// Servers without `[auth]`, and requests to public routes.
+    pub fn unrestricted() -> Self {
+        Self {
+            scope: AuthScope::ServerAdmin,
+            admin: false,
+            create_room: true,
+            join_any_room: true,
+            join_rooms: HashSet::new(),
+        }
+    }
+
+    /// Static credentials. Rooms can be created wherever the scope reaches
+    /// `/room/create`, and `server_admin` ones need no room keys.
+    fn from_scope(scope: AuthScope, create_scope: AuthScope) -> Self {
+        Self {
+            scope,
+            admin: scope == AuthScope::ServerAdmin,
+            create_room: scope >= create_scope,
+            join_any_room: true,
+            join_rooms: HashSet::new(),
+        }
+    }
+
+    /// Space separated `scope` claim values: `admin`, `room:create`,
+    /// `room:join` and `room:join:{room id}`. Unknown values are ignored.
+    fn from_scopes<'a>(scopes: impl Iterator<Item = &'a str>) -> Self {
+        let mut permissions = Self::default();
+        for scope in scopes {
+            match scope {
+                "admin" => permissions.admin = true,
+                "room:create" => permissions.create_room = true,
+                "room:join" => permissions.join_any_room = true,
+                _ => {
+                    if let Some(room_id) = scope
+                        .strip_prefix("room:join:")
+                        .and_then(|id| id.parse().ok())
+                    {
+                        permissions.join_rooms.insert(room_id);
+                    }
+                }
+            }
+        }
+        permissions.scope = if permissions.admin {
+            AuthScope::ServerAdmin
+        } else if permissions.create_room {
+            AuthScope::RoomAdmin
+        } else {
+            AuthScope::Client
+        };
+        permissions
+    }
+
+    pub fn can_create_room(&self) -> bool {
+        self.admin || self.create_room
+    }
+
+    pub fn can_join_room(&self, room_id: i32) -> bool {
+        self.admin || self.join_any_room || self.join_rooms.contains(&room_id)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scope {
+    Text(String),
+    List(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    #[serde(default)]
+    scope: Option<Scope>,
+}
+
+struct JwtKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Verifies Bearer JWTs against the keys of `[auth.jwt]`.
+pub struct JwtValidator {
+    keys: Vec<JwtKey>,
+    audience: Option<String>,
+    issuer: Option<String>,
+    leeway: u64,
+}
+
+impl JwtValidator {
+    pub fn from_config(jwt: &Jwt) -> anyhow::Result<Self> {
+        let mut keys = vec![];
+        if let Some(secret) = &jwt.secret {
+            keys.push(JwtKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+            });
+        }
+        if let Some(path) = &jwt.public_key {
+            let pem = fs::read(path).with_context(|| format!("read jwt public key {}", path))?;
+            let (algorithm, key) = match jwt.algorithm {
+                JwtAlgorithm::RS256 => (Algorithm::RS256, DecodingKey::from_rsa_pem(&pem)?),
+                JwtAlgorithm::EdDSA => (Algorithm::EdDSA, DecodingKey::from_ed_pem(&pem)?),
+            };
+            keys.push(JwtKey {
+                kid: None,
+                algorithm,
+                key,
+            });
+        }
+        if let Some(path) = &jwt.jwks {
+            let text = fs::read_to_string(path).with_context(|| format!("read jwks {}", path))?;
+            let set: JwkSet = serde_json::from_str(&text)?;
+            for jwk in set.keys.iter() {
+                keys.push(JwtKey {
+                    kid: jwk.common.key_id.clone(),
+                    algorithm: jwk_algorithm(jwk)?,
+                    key: DecodingKey::from_jwk(jwk)?,
+                });
+            }
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("[auth.jwt] needs a secret, public_key or jwks"));
+        }
+        Ok(Self {
+            keys,
+            audience: jwt.audience.clone(),
+            issuer: jwt.issuer.clone(),
+            leeway: jwt.leeway,
+        })
+    }
+
+    pub fn verify(&self, token: &str) -> anyhow::Result<Permissions> {
+        let header = jsonwebtoken::decode_header(token)?;
+        // The key decides the algorithm, never the token, so an HS256 token
+        // signed with a public key is not accepted.
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                key.algorithm == header.alg
+                    && (header.kid.is_none() || key.kid.is_none() || key.kid == header.kid)
+            })
+            .ok_or_else(|| anyhow!("no key for {:?} {:?}", header.alg, header.kid))?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.leeway = self.leeway;
+        validation.validate_nbf = true;
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let claims = jsonwebtoken::decode::<Claims>(token, &key.key, &validation)?.claims;
+        let permissions = match &claims.scope {
+            Some(Scope::Text(scope)) => Permissions::from_scopes(scope.split_whitespace()),
+            Some(Scope::List(scopes)) => {
+                Permissions::from_scopes(scopes.iter().map(|s| s.as_str()))
+            }
+            None => Permissions::default(),
+        };
+        Ok(permissions)
+    }
+}
+
+fn jwk_algorithm(jwk: &Jwk) -> anyhow::Result<Algorithm> {
+    if let Some(algorithm) = &jwk.common.key_algorithm {
+        return Ok(Algorithm::from_str(&format!("{:?}", algorithm))?);
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Ok(Algorithm::RS256),
+        AlgorithmParameters::OctetKeyPair(_) => Ok(Algorithm::EdDSA),
+        AlgorithmParameters::OctetKey(_) => Ok(Algorithm::HS256),
+        AlgorithmParameters::EllipticCurve(_) => Ok(Algorithm::ES256),
+    }
+}
+
+/// Scopes required unless `[auth.routes]` says otherwise. Every other route
+/// needs `Client`.
+pub fn default_routes() -> HashMap<String, AuthScope> {
+    HashMap::from([
+        ("/room/create".to_string(), AuthScope::RoomAdmin),
+        ("/room/delete".to_string(), AuthScope::RoomAdmin),
+        ("/room/kick".to_string(), AuthScope::RoomAdmin),
+        ("/metrics".to_string(), AuthScope::ServerAdmin),
+        ("/admin".to_string(), AuthScope::ServerAdmin),
+    ])
+}
+
+/// Route prefixes with their required scope, longest first.
+#[derive(Debug, Clone, Default)]
+struct RouteScopes {
+    routes: Vec<(String, AuthScope)>,
+}
+
+impl RouteScopes {
+    fn new(routes: HashMap<String, AuthScope>) -> Self {
+        let mut routes: Vec<(String, AuthScope)> = routes
+            .into_iter()
+            .map(|(path, scope)| (path.trim_end_matches('/').to_string(), scope))
+            .collect();
+        routes.sort_by_key(|(path, _)| Reverse(path.len()));
+        Self { routes }
+    }
+
+    /// A route matches on whole path segments, so `/room/invite` covers
+    /// `/room/invite/join/..` but not `/room/invited`. `/v2` routes need the
+    /// same scope as the original ones.
+    fn required(&self, path: &str) -> AuthScope {
+        let path = match path.strip_prefix("/v2") {
+            Some(rest) if rest.starts_with('/') => rest,
+            _ => path,
+        };
+        for (prefix, scope) in self.routes.iter() {
+            let is_match = path
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+            if is_match {
+                return *scope;
+            }
+        }
+        AuthScope::Client
+    }
+}
 
-#[derive(Debug)]
 pub struct ManyValidate<ResBody> {
-    header_values: HashSet<String>,
+    header_values: HashMap<String, AuthScope>,
+    jwt: Option<Arc<JwtValidator>>,
+    routes: Arc<RouteScopes>,
     _ty: PhantomData<fn() -> ResBody>,
 }
 
@@ -17,45 +273,224 @@ impl<ResBody> ManyValidate<ResBody> {
     where
         ResBody: Body + Default,
     {
-        let mut header_values = HashSet::new();
+        let mut header_values = HashMap::new();
+        let mut jwt = None;
+        let mut routes = default_routes();
         for auth in auths {
-            for authorization in auth.to_authorizations().into_iter() {
-                header_values.insert(authorization.parse().unwrap());
+            for (authorization, scope) in auth.to_authorizations().into_iter() {
+                header_values.insert(authorization, scope);
+            }
+            routes.extend(auth.routes.clone());
+            if let Some(config) = &auth.jwt {
+                jwt = Some(Arc::new(
+                    JwtValidator::from_config(config).expect("jwt config error"),
+                ));
             }
         }
         Self {
             header_values,
+            jwt,
+            routes: Arc::new(RouteScopes::new(routes)),
             _ty: PhantomData,
         }
     }
+
+    fn permissions(&self, authorization: Option<&str>) -> Option<Permissions> {
+        let authorization = authorization?;
+        if let Some(scope) = self.header_values.get(authorization) {
+            let create_scope = self.routes.required("/room/create");
+            return Some(Permissions::from_scope(*scope, create_scope));
+        }
+        let token = authorization.strip_prefix("Bearer ")?;
+        match self.jwt.as_ref()?.verify(token) {
+            Ok(permissions) => Some(permissions),
+            Err(err) => {
+                debug!("jwt rejected: {}", err);
+                None
+            }
+        }
+    }
 }
 
 impl<ResBody> Clone for ManyValidate<ResBody> {
     fn clone(&self) -> Self {
         Self {
             header_values: self.header_values.clone(),
+            jwt: self.jwt.clone(),
+            routes: self.routes.clone(),
             _ty: PhantomData,
         }
     }
 }
 
+/// A JSON error response, as the handlers would send it.
+fn error_response<ResBody>(err: AppError) -> Response<ResBody>
+where
+    ResBody: From<String>,
+{
+    let mut res = Response::new(ResBody::from(err.to_json()));
+    *res.status_mut() = err.status();
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    res
+}
+
 impl<B, ResBody> ValidateRequest<B> for ManyValidate<ResBody>
 where
-    ResBody: Body + Default,
+    ResBody: Body + Default + From<String>,
 {
     type ResponseBody = ResBody;
 
     fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
-        if self.header_values.is_empty() {
+        if self.header_values.is_empty() && self.jwt.is_none() {
+            request.extensions_mut().insert(Permissions::unrestricted());
             return Ok(());
         }
-        match request.headers().get(header::AUTHORIZATION) {
-            Some(actual) if self.header_values.contains(actual.to_str().unwrap()) => Ok(()),
-            _ => {
-                let mut res = Response::new(ResBody::default());
-                *res.status_mut() = StatusCode::UNAUTHORIZED;
-                Err(res)
-            }
+        let required = self.routes.required(request.uri().path());
+        let authorization = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        let permissions = match self.permissions(authorization) {
+            Some(permissions) => permissions,
+            None if required == AuthScope::Public => Permissions::unrestricted(),
+            None => return Err(error_response(AppError::Unauthorized)),
+        };
+        if permissions.scope < required {
+            return Err(error_response(AppError::permission_denied(format!(
+                "credential scope {:?} is below {:?}",
+                permissions.scope, required
+            ))));
         }
+        request.extensions_mut().insert(permissions);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn from_claim(scope: &str) -> Permissions {
+        Permissions::from_scopes(scope.split_whitespace())
+    }
+
+    fn validator() -> JwtValidator {
+        JwtValidator::from_config(&Jwt {
+            secret: Some("secret".to_string()),
+            public_key: None,
+            algorithm: Default::default(),
+            jwks: None,
+            audience: None,
+            issuer: None,
+            leeway: 0,
+        })
+        .unwrap()
+    }
+
+    fn sign(claims: serde_json::Value, secret: &str) -> String {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn exp() -> i64 {
+        chrono::Utc::now().timestamp() + 60
+    }
+
+    #[test]
+    fn admin_scope() {
+        let permissions = from_claim("admin");
+        assert_eq!(permissions.scope, AuthScope::ServerAdmin);
+        assert!(permissions.admin);
+        assert!(permissions.can_create_room());
+        assert!(permissions.can_join_room(7));
+    }
+
+    #[test]
+    fn create_and_join_scopes() {
+        let permissions = from_claim("room:create room:join");
+        assert_eq!(permissions.scope, AuthScope::RoomAdmin);
+        assert!(!permissions.admin);
+        assert!(permissions.can_create_room());
+        assert!(permissions.can_join_room(7));
+
+        let permissions = from_claim("room:join");
+        assert_eq!(permissions.scope, AuthScope::Client);
+        assert!(!permissions.can_create_room());
+        assert!(permissions.can_join_room(7));
+    }
+
+    #[test]
+    fn join_scope_for_listed_rooms_only() {
+        let permissions = from_claim("room:join:7 room:join:-3");
+        assert_eq!(permissions.scope, AuthScope::Client);
+        assert!(permissions.can_join_room(7));
+        assert!(permissions.can_join_room(-3));
+        assert!(!permissions.can_join_room(8));
+    }
+
+    #[test]
+    fn unknown_scopes_are_ignored() {
+        let permissions = from_claim("room:join:x room:delete openid ADMIN");
+        assert_eq!(permissions.scope, AuthScope::Client);
+        assert!(!permissions.admin);
+        assert!(!permissions.can_create_room());
+        assert!(!permissions.can_join_room(7));
+        assert!(permissions.join_rooms.is_empty());
+    }
+
+    #[test]
+    fn static_credential_scope() {
+        let permissions = Permissions::from_scope(AuthScope::RoomAdmin, AuthScope::RoomAdmin);
+        assert!(!permissions.admin);
+        assert!(permissions.can_create_room());
+        assert!(permissions.can_join_room(7));
+
+        let permissions = Permissions::from_scope(AuthScope::Client, AuthScope::RoomAdmin);
+        assert!(!permissions.can_create_room());
+
+        let permissions = Permissions::from_scope(AuthScope::ServerAdmin, AuthScope::RoomAdmin);
+        assert!(permissions.admin);
+    }
+
+    #[test]
+    fn jwt_scope_claim_as_text_or_list() {
+        let validator = validator();
+
+        let token = sign(json!({"exp": exp(), "scope": "room:create"}), "secret");
+        let permissions = validator.verify(&token).unwrap();
+        assert_eq!(permissions.scope, AuthScope::RoomAdmin);
+
+        let token = sign(
+            json!({"exp": exp(), "scope": ["room:join:7", "admin"]}),
+            "secret",
+        );
+        let permissions = validator.verify(&token).unwrap();
+        assert!(permissions.admin);
+        assert!(permissions.join_rooms.contains(&7));
+
+        let token = sign(json!({"exp": exp()}), "secret");
+        let permissions = validator.verify(&token).unwrap();
+        assert_eq!(permissions.scope, AuthScope::Client);
+        assert!(!permissions.can_join_room(7));
+    }
+
+    #[test]
+    fn jwt_with_other_key_or_expired_is_rejected() {
+        let validator = validator();
+
+        let token = sign(json!({"exp": exp(), "scope": "admin"}), "other");
+        assert!(validator.verify(&token).is_err());
+
+        let expired = chrono::Utc::now().timestamp() - 60;
+        let token = sign(json!({"exp": expired, "scope": "admin"}), "secret");
+        assert!(validator.verify(&token).is_err());
     }
 }
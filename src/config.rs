@@ -1,7 +1,7 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::{env, fs, net::SocketAddr, str::FromStr};
+use std::{collections::HashMap, env, fs, net::SocketAddr, str::FromStr};
 use webrtc::{ice, ice_transport::ice_server::RTCIceServer, Error};
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
@@ -16,6 +16,8 @@ pub struct Config {
     pub log: Log,
     #[serde(default)]
     pub stream_info: StreamInfo,
+    #[serde(default)]
+    pub room: Room,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -32,21 +34,171 @@ pub struct Auth {
     pub accounts: Vec<Account>,
     #[serde(default)]
     pub tokens: Vec<String>,
+    #[serde(default)]
+    pub jwt: Option<Jwt>,
+    #[serde(default)]
+    pub webhook: Option<Webhook>,
+    #[serde(default)]
+    pub credentials: Vec<Credential>,
+    /// Scope required by the routes under a path, the longest matching path
+    /// wins. Merged over the defaults of `auth::default_routes`.
+    #[serde(default)]
+    pub routes: HashMap<String, AuthScope>,
+    #[serde(default)]
+    pub lockout: Lockout,
 }
 
 impl Auth {
-    pub fn to_authorizations(&self) -> Vec<String> {
+    /// Whether some caller can reach `server_admin` routes, with a static
+    /// credential or a JWT carrying "admin".
+    pub fn has_server_admin(&self) -> bool {
+        !self.accounts.is_empty()
+            || !self.tokens.is_empty()
+            || self.jwt.is_some()
+            || self
+                .credentials
+                .iter()
+                .any(|credential| credential.scope == AuthScope::ServerAdmin)
+    }
+
+    /// Every static `Authorization` header value with its scope. `tokens`
+    /// and `accounts` predate scopes and may do everything.
+    pub fn to_authorizations(&self) -> Vec<(String, AuthScope)> {
         let mut authorizations = vec![];
         for account in self.accounts.iter() {
-            authorizations.push(account.to_authorization());
+            authorizations.push((account.to_authorization(), AuthScope::ServerAdmin));
         }
         for token in self.tokens.iter() {
-            authorizations.push(format!("Bearer {}", token));
+            authorizations.push((format!("Bearer {}", token), AuthScope::ServerAdmin));
+        }
+        for credential in self.credentials.iter() {
+            authorizations.push((credential.to_authorization(), credential.scope));
         }
         authorizations
     }
 }
 
+/// Bearer JWTs minted by a game backend. Keys come from `secret` (HS256),
+/// a PEM `public_key` file (RS256 or EdDSA, see `algorithm`) and/or a local
+/// `jwks` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Jwt {
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub public_key: Option<String>,
+    #[serde(default)]
+    pub algorithm: JwtAlgorithm,
+    #[serde(default)]
+    pub jwks: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Seconds of clock skew tolerated on `exp` and `nbf`.
+    #[serde(default)]
+    pub leeway: u64,
+}
+
+/// Account service asked whether a join, room creation, publish or subscribe
+/// may go ahead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Sent as the `Authorization` header of the callout.
+    #[serde(default)]
+    pub authorization: Option<String>,
+    #[serde(default)]
+    pub timeout: WebhookTimeout,
+    /// Milliseconds a decision is reused for identical requests, 0 to never
+    /// cache.
+    #[serde(default)]
+    pub cache_ttl: u64,
+    /// Allow the request when the service fails or times out.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookTimeout(pub u64);
+
+impl Default for WebhookTimeout {
+    fn default() -> Self {
+        WebhookTimeout(2000)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JwtAlgorithm {
+    #[default]
+    RS256,
+    EdDSA,
+}
+
+/// Back-off for remote ips and rooms after failed shared key, master key or
+/// token checks. Times are in milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lockout {
+    /// Failures of one ip within `window` before it is locked, 0 disables.
+    pub max_failures: u32,
+    /// Failures against one room within `window`, from any ip, before the
+    /// room is locked, 0 disables.
+    pub room_max_failures: u32,
+    pub base_delay: u64,
+    pub max_delay: u64,
+    pub window: u64,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            room_max_failures: 100,
+            base_delay: 1000,
+            max_delay: 900000,
+            window: 600000,
+        }
+    }
+}
+
+/// What a credential may reach, each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthScope {
+    /// Only usable in `routes`, the route needs no credential at all.
+    Public,
+    #[default]
+    Client,
+    RoomAdmin,
+    ServerAdmin,
+}
+
+/// A Bearer `token`, or a Basic account when `token` is empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credential {
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    pub scope: AuthScope,
+}
+
+impl Credential {
+    pub fn to_authorization(&self) -> String {
+        if !self.token.is_empty() {
+            return format!("Bearer {}", self.token);
+        }
+        Account {
+            username: self.username.clone(),
+            password: self.password.clone(),
+        }
+        .to_authorization()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     #[serde(default)]
@@ -89,6 +241,65 @@ pub struct StreamInfo {
     pub publish_leave_timeout: PublishLeaveTimeout,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Room {
+    #[serde(default)]
+    pub store: RoomStoreKind,
+    /// Defaults to a file named after the store kind.
+    #[serde(default)]
+    pub store_path: Option<String>,
+    #[serde(default)]
+    pub idle_timeout: RoomIdleTimeout,
+    #[serde(default)]
+    pub resume_grace: u64,
+    #[serde(default)]
+    pub token_ttl: RoomTokenTtl,
+    #[serde(default)]
+    pub event_history: RoomEventHistory,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoomIdleTimeout(pub u64);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomTokenTtl(pub u64);
+
+impl Default for RoomTokenTtl {
+    fn default() -> Self {
+        RoomTokenTtl(86400000)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomEventHistory(pub usize);
+
+impl Default for RoomEventHistory {
+    fn default() -> Self {
+        RoomEventHistory(256)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomStoreKind {
+    #[default]
+    Memory,
+    Json,
+    Sqlite,
+}
+
+impl Room {
+    pub fn store_path(&self) -> String {
+        match &self.store_path {
+            Some(path) => path.clone(),
+            None => match self.store {
+                RoomStoreKind::Sqlite => String::from("rooms.db"),
+                _ => String::from("rooms.json"),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetaDataPubMax(pub u64);
 
@@ -243,6 +454,9 @@ impl Config {
                 "stream_info.pub_max cannot be greater than stream_info.sub_max"
             ));
         }
+        if self.room.store != RoomStoreKind::Memory && self.room.store_path().trim().is_empty() {
+            return Err(anyhow::anyhow!("room.store_path cannot be empty"));
+        }
         for ice_server in self.ice_servers.iter() {
             ice_server
                 .validate()
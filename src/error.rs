@@ -1,11 +1,84 @@
+use axum::http::{header, HeaderValue};
 use axum::response::{IntoResponse, Response};
 use http::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+/// Stable `code` of an error response. Clients should branch on the code
+/// and the status, never on the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// 422, the arguments can not be parsed or are invalid.
+    InvalidRequest,
+    /// 401, no or an unknown `Authorization` header.
+    Unauthorized,
+    /// 401
+    InvalidSharedKey,
+    /// 401
+    InvalidMasterKey,
+    /// 401, the user's token is wrong, expired or revoked.
+    InvalidToken,
+    /// 403, the credential's scope or the user's role does not allow it.
+    PermissionDenied,
+    /// 403, the name or the ip of the user was banned from the room.
+    Banned,
+    /// 403, the auth webhook denied the request.
+    WebhookDenied,
+    /// 404
+    RoomNotFound,
+    /// 404, the user is not, or no longer, in the room.
+    UserNotFound,
+    /// 404
+    StreamNotFound,
+    /// 404, the WebRTC session is closed or was never opened.
+    SessionNotFound,
+    /// 409
+    StreamAlreadyExists,
+    /// 409, no free player or spectator slot.
+    RoomFull,
+    /// 409, e.g. the host slot is taken or a property version is stale.
+    Conflict,
+    /// 429, see `details.retry_after` and the `Retry-After` header.
+    TooManyAttempts,
+    /// 503, e.g. the auth webhook can not be reached.
+    ServiceUnavailable,
+    /// 500
+    Internal,
+}
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorJson {
+    code: ErrorCode,
+    message: String,
+    /// `reason` for `webhook_denied`, `user_id` for `user_not_found` and
+    /// `retry_after` in milliseconds for `too_many_attempts`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
 
 #[derive(Debug)]
 pub enum AppError {
+    InvalidRequest(String),
+    Unauthorized,
+    InvalidSharedKey,
+    InvalidMasterKey,
+    InvalidToken,
+    PermissionDenied(String),
+    Banned,
+    WebhookDenied(Option<String>),
+    RoomNotFound,
+    UserNotFound(i32),
     StreamNotFound(String),
+    SessionNotFound,
     StreamAlreadyExists(String),
-    Throw(String),
+    RoomFull,
+    Conflict(String),
+    /// Milliseconds until the next attempt may be made.
+    TooManyAttempts(i64),
+    ServiceUnavailable(String),
     InternalServerError(anyhow::Error),
 }
 
@@ -24,24 +97,130 @@ impl AppError {
         AppError::StreamAlreadyExists(t.to_string())
     }
 
-    pub fn throw<T>(t: T) -> Self
+    pub fn invalid_request<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::InvalidRequest(t.to_string())
+    }
+
+    pub fn permission_denied<T>(t: T) -> Self
     where
         T: ToString,
     {
-        AppError::Throw(t.to_string())
+        AppError::PermissionDenied(t.to_string())
+    }
+
+    pub fn conflict<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::Conflict(t.to_string())
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            AppError::Unauthorized => ErrorCode::Unauthorized,
+            AppError::InvalidSharedKey => ErrorCode::InvalidSharedKey,
+            AppError::InvalidMasterKey => ErrorCode::InvalidMasterKey,
+            AppError::InvalidToken => ErrorCode::InvalidToken,
+            AppError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            AppError::Banned => ErrorCode::Banned,
+            AppError::WebhookDenied(_) => ErrorCode::WebhookDenied,
+            AppError::RoomNotFound => ErrorCode::RoomNotFound,
+            AppError::UserNotFound(_) => ErrorCode::UserNotFound,
+            AppError::StreamNotFound(_) => ErrorCode::StreamNotFound,
+            AppError::SessionNotFound => ErrorCode::SessionNotFound,
+            AppError::StreamAlreadyExists(_) => ErrorCode::StreamAlreadyExists,
+            AppError::RoomFull => ErrorCode::RoomFull,
+            AppError::Conflict(_) => ErrorCode::Conflict,
+            AppError::TooManyAttempts(_) => ErrorCode::TooManyAttempts,
+            AppError::ServiceUnavailable(_) => ErrorCode::ServiceUnavailable,
+            AppError::InternalServerError(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code() {
+            ErrorCode::InvalidRequest => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Unauthorized
+            | ErrorCode::InvalidSharedKey
+            | ErrorCode::InvalidMasterKey
+            | ErrorCode::InvalidToken => StatusCode::UNAUTHORIZED,
+            ErrorCode::PermissionDenied | ErrorCode::Banned | ErrorCode::WebhookDenied => {
+                StatusCode::FORBIDDEN
+            }
+            ErrorCode::RoomNotFound
+            | ErrorCode::UserNotFound
+            | ErrorCode::StreamNotFound
+            | ErrorCode::SessionNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::StreamAlreadyExists | ErrorCode::RoomFull | ErrorCode::Conflict => {
+                StatusCode::CONFLICT
+            }
+            ErrorCode::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AppError::InvalidRequest(err) => err.clone(),
+            AppError::Unauthorized => "missing or unknown credential".to_string(),
+            AppError::InvalidSharedKey => "invalid shared key".to_string(),
+            AppError::InvalidMasterKey => "invalid master key".to_string(),
+            AppError::InvalidToken => "invalid token".to_string(),
+            AppError::PermissionDenied(err) => err.clone(),
+            AppError::Banned => "banned from the room".to_string(),
+            AppError::WebhookDenied(_) => "denied by the auth webhook".to_string(),
+            AppError::RoomNotFound => "room not found".to_string(),
+            AppError::UserNotFound(_) => "user not found".to_string(),
+            AppError::StreamNotFound(err) => err.clone(),
+            AppError::SessionNotFound => "session not found".to_string(),
+            AppError::StreamAlreadyExists(err) => err.clone(),
+            AppError::RoomFull => "room is full".to_string(),
+            AppError::Conflict(err) => err.clone(),
+            AppError::TooManyAttempts(_) => "too many failed attempts".to_string(),
+            AppError::ServiceUnavailable(err) => err.clone(),
+            AppError::InternalServerError(err) => err.to_string(),
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            AppError::WebhookDenied(Some(reason)) => Some(json!({ "reason": reason })),
+            AppError::UserNotFound(user_id) => Some(json!({ "user_id": user_id })),
+            AppError::TooManyAttempts(retry_after) => Some(json!({ "retry_after": retry_after })),
+            _ => None,
+        }
+    }
+
+    /// The JSON body of the error response.
+    pub fn to_json(&self) -> String {
+        let body = ErrorJson {
+            code: self.code(),
+            message: self.message(),
+            details: self.details(),
+        };
+        serde_json::to_string(&body).unwrap()
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        match self {
-            AppError::StreamNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
-            AppError::StreamAlreadyExists(err) => (StatusCode::CONFLICT, err).into_response(),
-            AppError::InternalServerError(err) => {
-                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
-            }
-            AppError::Throw(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        let headers = [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )];
+        let mut response = (self.status(), headers, self.to_json()).into_response();
+        if let AppError::TooManyAttempts(retry_after) = self {
+            let seconds = (retry_after + 999) / 1000;
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
         }
+        response
     }
 }
 
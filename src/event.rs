@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use crate::forward::rtc::message::{ForwardEvent, ForwardEventType};
+
+/// What happened in a room, as sent by `/room/{room_id}/events`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    UserJoin {
+        user_id: i32,
+        name: String,
+    },
+    UserLeave {
+        user_id: i32,
+    },
+    StreamCreate {
+        stream: String,
+    },
+    StreamDestroy {
+        stream: String,
+    },
+    PublishUp {
+        stream: String,
+        session: String,
+        user_id: u32,
+    },
+    PublishDown {
+        stream: String,
+        session: String,
+        user_id: u32,
+    },
+    SubscribeUp {
+        stream: String,
+        session: String,
+        user_id: u32,
+    },
+    SubscribeDown {
+        stream: String,
+        session: String,
+        user_id: u32,
+    },
+    /// The last event of the room.
+    RoomDelete,
+}
+
+impl From<ForwardEvent> for RoomEvent {
+    fn from(event: ForwardEvent) -> Self {
+        let (stream, session, user_id) = (event.stream_info.id, event.session, event.user);
+        match event.r#type {
+            ForwardEventType::PublishUp => RoomEvent::PublishUp {
+                stream,
+                session,
+                user_id,
+            },
+            ForwardEventType::PublishDown => RoomEvent::PublishDown {
+                stream,
+                session,
+                user_id,
+            },
+            ForwardEventType::SubscribeUp => RoomEvent::SubscribeUp {
+                stream,
+                session,
+                user_id,
+            },
+            ForwardEventType::SubscribeDown => RoomEvent::SubscribeDown {
+                stream,
+                session,
+                user_id,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RoomEventEntry {
+    /// Counts up from 1 in each room, sent as the SSE `id`.
+    pub id: u64,
+    pub time: i64,
+    #[serde(flatten)]
+    pub event: RoomEvent,
+}
+
+struct History {
+    next_id: u64,
+    entries: VecDeque<RoomEventEntry>,
+}
+
+/// Events of a room, with the last `capacity` of them kept so that
+/// listeners can resume after `Last-Event-ID`.
+pub struct RoomEvents {
+    capacity: usize,
+    history: Mutex<History>,
+    sender: broadcast::Sender<RoomEventEntry>,
+}
+
+impl RoomEvents {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(16));
+        Self {
+            capacity,
+            history: Mutex::new(History {
+                next_id: 1,
+                entries: VecDeque::new(),
+            }),
+            sender,
+        }
+    }
+
+    pub fn send(&self, event: RoomEvent) {
+        let mut history = self.history.lock().unwrap();
+        let entry = RoomEventEntry {
+            id: history.next_id,
+            time: Utc::now().timestamp_millis(),
+            event,
+        };
+        history.next_id += 1;
+        if self.capacity > 0 {
+            if history.entries.len() == self.capacity {
+                history.entries.pop_front();
+            }
+            history.entries.push_back(entry.clone());
+        }
+        let _ = self.sender.send(entry);
+    }
+
+    /// The kept events after `last_event_id`, none without one, and the
+    /// receiver of the events after them. Events older than the history are
+    /// lost.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<RoomEventEntry>, broadcast::Receiver<RoomEventEntry>) {
+        // Under the lock no event can fall between the two
+        let history = self.history.lock().unwrap();
+        let missed = match last_event_id {
+            Some(last_event_id) => history
+                .entries
+                .iter()
+                .filter(|entry| entry.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        (missed, self.sender.subscribe())
+    }
+}
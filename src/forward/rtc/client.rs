@@ -1,29 +1,116 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
 
+use chrono::Utc;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use tokio::sync::RwLock;
 
 use crate::{error::AppError, Result};
 
+/// Digest of a session token, kept wherever a token has to be checked later
+/// instead of the token itself.
+#[derive(Clone, Copy)]
+pub struct TokenDigest([u8; 32]);
+
+impl TokenDigest {
+    pub fn new(token: &str) -> Self {
+        Self(Sha256::digest(token.as_bytes()).into())
+    }
+
+    /// Compares in constant time.
+    pub fn matches(&self, other: &TokenDigest) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+/// Only the digest of a session token is kept, so tokens can not be read
+/// back from memory or logs.
+#[derive(Clone, Copy)]
+struct SessionToken {
+    digest: TokenDigest,
+    /// Unix time in milliseconds, 0 if the token never expires.
+    expires_at: i64,
+    revoked: bool,
+}
+
+impl SessionToken {
+    fn new(token: &str, expires_at: i64) -> Self {
+        Self {
+            digest: TokenDigest::new(token),
+            expires_at,
+            revoked: false,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
-    _name: String,
+    name: String,
     _id: i32,
-    token: u32,
+    token: Arc<Mutex<SessionToken>>,
+    join_time: i64,
+    remote_ip: IpAddr,
     stream_map: Arc<RwLock<Vec<String>>>,
+    properties: Arc<RwLock<HashMap<String, Value>>>,
 }
 
 impl Client {
-    pub async fn new(id: i32, token: u32, name: String) -> Result<Self> {
+    pub async fn new(
+        id: i32,
+        token: &str,
+        expires_at: i64,
+        name: String,
+        remote_ip: IpAddr,
+    ) -> Result<Self> {
         Ok(Self {
-            _name: name,
+            name: name,
             _id: id,
-            token: token,
+            token: Arc::new(Mutex::new(SessionToken::new(token, expires_at))),
+            join_time: Utc::now().timestamp_millis(),
+            remote_ip: remote_ip,
             stream_map: Arc::new(RwLock::new(Vec::new())),
+            properties: Default::default(),
         })
     }
 
-    pub fn check_token(&self, token: u32) -> bool {
-        self.token == token
+    pub fn check_token(&self, token: &str) -> bool {
+        let session = *self.token.lock().unwrap();
+        let is_match = session.digest.matches(&TokenDigest::new(token));
+        let is_expired =
+            session.expires_at > 0 && Utc::now().timestamp_millis() >= session.expires_at;
+        is_match && !is_expired && !session.revoked
+    }
+
+    /// Replaces the token, which invalidates the previous one.
+    pub fn set_token(&self, token: &str, expires_at: i64) {
+        *self.token.lock().unwrap() = SessionToken::new(token, expires_at);
+    }
+
+    pub fn revoke_token(&self) {
+        self.token.lock().unwrap().revoked = true;
+    }
+
+    pub fn token_expires_at(&self) -> i64 {
+        self.token.lock().unwrap().expires_at
+    }
+
+    pub fn id(&self) -> i32 {
+        self._id
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn remote_ip(&self) -> IpAddr {
+        self.remote_ip
+    }
+
+    pub fn join_time(&self) -> i64 {
+        self.join_time
     }
 }
 
@@ -56,3 +143,76 @@ impl Client {
         return streams.iter().map(|stream| stream.clone()).collect();
     }
 }
+
+impl Client {
+    pub async fn properties(&self) -> HashMap<String, Value> {
+        self.properties.read().await.clone()
+    }
+
+    /// Merges the given keys into the user's properties. A `null` value
+    /// removes the key.
+    pub async fn set_properties(&self, updates: HashMap<String, Option<Value>>) {
+        let mut properties = self.properties.write().await;
+        for (key, value) in updates.into_iter() {
+            match value {
+                Some(value) => properties.insert(key, value),
+                None => properties.remove(&key),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    async fn client(token: &str, expires_at: i64) -> Client {
+        Client::new(0, token, expires_at, "u".to_string(), IP)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn digest_matches_only_the_same_token() {
+        let digest = TokenDigest::new("token");
+        assert!(digest.matches(&TokenDigest::new("token")));
+        assert!(!digest.matches(&TokenDigest::new("token2")));
+        assert!(!digest.matches(&TokenDigest::new("")));
+    }
+
+    #[tokio::test]
+    async fn token_without_expiry_stays_valid() {
+        let client = client("token", 0).await;
+        assert!(client.check_token("token"));
+        assert!(!client.check_token("other"));
+        assert_eq!(client.token_expires_at(), 0);
+    }
+
+    #[tokio::test]
+    async fn token_expires() {
+        let now = Utc::now().timestamp_millis();
+        assert!(client("token", now + 60_000).await.check_token("token"));
+        assert!(!client("token", now).await.check_token("token"));
+        assert!(!client("token", now - 1).await.check_token("token"));
+    }
+
+    #[tokio::test]
+    async fn revoked_token_is_rejected() {
+        let client = client("token", 0).await;
+        client.clone().revoke_token();
+        assert!(!client.check_token("token"));
+    }
+
+    #[tokio::test]
+    async fn set_token_replaces_the_old_one() {
+        let client = client("old", 0).await;
+        client.revoke_token();
+        let expires_at = Utc::now().timestamp_millis() + 60_000;
+        client.set_token("new", expires_at);
+        assert!(!client.check_token("old"));
+        assert!(client.check_token("new"));
+        assert_eq!(client.token_expires_at(), expires_at);
+    }
+}
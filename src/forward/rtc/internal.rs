@@ -1,12 +1,18 @@
 use std::borrow::ToOwned;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::vec;
 
+use crate::event::{RoomEvent, RoomEvents};
+use crate::forward::rtc::client::TokenDigest;
 use crate::forward::rtc::message::ForwardInfo;
+use crate::property::RoomProperties;
 use crate::result::Result;
+use crate::role::{Permission, RoomRoles};
 use chrono::Utc;
 
+use libws::{event_message, EventType};
 use tokio::sync::{broadcast, RwLock};
 use tracing::{debug, info};
 use webrtc::api::interceptor_registry::register_default_interceptors;
@@ -66,12 +72,28 @@ pub(crate) struct PeerForwardInternal {
     subscribe_group: RwLock<Vec<SubscribeRTCPeerConnection>>,
     user_sender_map: Arc<RwLock<HashMap<u32, broadcast::Sender<Vec<u8>>>>>,
     data_channel_forward: DataChannelForward,
+    properties: Arc<RwLock<RoomProperties>>,
+    roles: Arc<RwLock<RoomRoles>>,
+    resume_grace: u64,
+    /// Users whose data channel dropped and whose OnClose is held back, with
+    /// the digest of the token they connected with and the time the channel dropped.
+    detached_map: Arc<RwLock<HashMap<u32, (TokenDigest, i64)>>>,
     ice_server: Vec<RTCIceServer>,
     event_sender: broadcast::Sender<ForwardEvent>,
+    events: Arc<RoomEvents>,
+    /// Room events of the stream until `announce`, none once announced.
+    held_events: Mutex<Option<Vec<RoomEvent>>>,
 }
 
 impl PeerForwardInternal {
-    pub(crate) fn new(stream: impl ToString, ice_server: Vec<RTCIceServer>) -> Self {
+    pub(crate) fn new(
+        stream: impl ToString,
+        ice_server: Vec<RTCIceServer>,
+        properties: Arc<RwLock<RoomProperties>>,
+        roles: Arc<RwLock<RoomRoles>>,
+        resume_grace: u64,
+        events: Arc<RoomEvents>,
+    ) -> Self {
         let publish_tracks_change = broadcast::channel(16);
         let data_channel_forward_channel = broadcast::channel(64);
         let data_channel_forward = DataChannelForward {
@@ -92,8 +114,27 @@ impl PeerForwardInternal {
             subscribe_group: RwLock::new(Vec::new()),
             user_sender_map: Arc::new(RwLock::new(HashMap::new())),
             data_channel_forward,
+            properties,
+            roles,
+            resume_grace,
+            detached_map: Default::default(),
             ice_server,
             event_sender,
+            events,
+            held_events: Mutex::new(Some(vec![])),
+        }
+    }
+
+    /// Sends `StreamCreate` and the room events held back until now. Called
+    /// once the stream is in the stream map, a forward that never gets there
+    /// is never heard of.
+    pub(crate) fn announce(&self) {
+        let mut held_events = self.held_events.lock().unwrap();
+        self.events.send(RoomEvent::StreamCreate {
+            stream: self.stream.clone(),
+        });
+        for event in held_events.take().unwrap_or_default() {
+            self.events.send(event);
         }
     }
 
@@ -136,6 +177,74 @@ impl PeerForwardInternal {
     //     Ok(false)
     // }
 
+    /// The publish or subscribe peer whose session id is `id`.
+    pub(crate) async fn get_peer(&self, id: String) -> Option<Arc<RTCPeerConnection>> {
+        let publish = self.publish.read().await;
+        if let Some(publish) = publish.as_ref() {
+            if publish.id == id {
+                return Some(publish.peer.clone());
+            }
+        }
+        drop(publish);
+
+        let subscribe_group = self.subscribe_group.read().await;
+        subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .map(|subscribe| subscribe.peer.clone())
+    }
+
+    /// The user owning the publish or subscribe session `id`.
+    pub(crate) async fn session_user(&self, id: String) -> Option<u32> {
+        let publish = self.publish.read().await;
+        if let Some(publish) = publish.as_ref() {
+            if publish.id == id {
+                return Some(publish.user);
+            }
+        }
+        drop(publish);
+
+        let subscribe_group = self.subscribe_group.read().await;
+        subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .map(|subscribe| subscribe.user)
+    }
+
+    /// Ends the publish or subscribe session `id`. The stream forgets it
+    /// through `remove_publish` or `remove_subscribe` before its peer is
+    /// closed, so the state change handler finds nothing left to remove.
+    pub(crate) async fn remove_session(&self, id: String) -> Result<()> {
+        let publish = self
+            .publish
+            .read()
+            .await
+            .as_ref()
+            .filter(|publish| publish.id == id)
+            .map(|publish| (publish.user, publish.peer.clone()));
+        if let Some((user, peer)) = publish {
+            self.remove_publish(user, peer.clone()).await?;
+            peer.close().await?;
+            return Ok(());
+        }
+
+        let subscribe = self
+            .subscribe_group
+            .read()
+            .await
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .map(|subscribe| (subscribe.user, subscribe.peer.clone()));
+        match subscribe {
+            Some((user, peer)) => {
+                self.remove_subscribe(user, peer.clone()).await?;
+                peer.close().await?;
+                Ok(())
+            }
+            None => Err(AppError::SessionNotFound),
+        }
+    }
+
     pub(crate) async fn close(&self) -> Result<()> {
         let publish = self.publish.read().await;
         let subscribe_group = self.subscribe_group.read().await;
@@ -151,10 +260,14 @@ impl PeerForwardInternal {
 
     async fn data_channel_forward(
         id: u32,
+        token: TokenDigest,
         dc: Arc<RTCDataChannel>,
         group_sender: broadcast::Sender<Vec<u8>>,
         user_sender: broadcast::Sender<Vec<u8>>,
         user_sender_map: Arc<RwLock<HashMap<u32, broadcast::Sender<Vec<u8>>>>>,
+        properties: Arc<RwLock<RoomProperties>>,
+        roles: Arc<RwLock<RoomRoles>>,
+        detached_map: Arc<RwLock<HashMap<u32, (TokenDigest, i64)>>>,
     ) {
         let dc2 = dc.clone();
         dc.on_open(Box::new(move || {
@@ -166,12 +279,33 @@ impl PeerForwardInternal {
                         return;
                     }
                 };
+                // Late joiners receive the whole property set before anything else
+                let message = properties.read().await.full_message();
+                if let Err(err) = raw.write(&message.into()).await {
+                    debug!("write data channel err: {}", err);
+                    return;
+                }
+                // A user coming back with its token within the resume grace
+                // was never announced as closed, so it is not announced as
+                // opened either. Another token ends the dropped channel first.
+                let detached = detached_map.write().await.remove(&id);
+                let is_resumed = match detached {
+                    Some((detached_token, _)) if detached_token.matches(&token) => true,
+                    Some(_) => {
+                        let _ = group_sender.send(event_message(EventType::Close, id, id));
+                        false
+                    }
+                    None => false,
+                };
+                let notice_open = !is_resumed && roles.read().await.notices_events(id as i32);
                 let r = Arc::clone(&raw);
                 tokio::spawn(Self::data_channel_read_loop(
                     id.clone(),
                     r,
                     group_sender.clone(),
                     user_sender_map,
+                    roles,
+                    notice_open,
                 ));
                 tokio::spawn(Self::data_channel_write_loop(
                     id.clone(),
@@ -194,22 +328,22 @@ impl PeerForwardInternal {
         d: Arc<DataChannel>,
         group_sender: broadcast::Sender<Vec<u8>>,
         user_sender_map: Arc<RwLock<HashMap<u32, broadcast::Sender<Vec<u8>>>>>,
+        roles: Arc<RwLock<RoomRoles>>,
+        notice_open: bool,
     ) {
         let mut buffer = vec![0u8; 9 + MESSAGE_SIZE]; // typ (1) + from (0 ~ 3) + to (4 ~ 7)
 
-        buffer[0] = 1; // typ: open
-        for i in 0..4 {
-            buffer[i + 1] = (id >> (i * 8)) as u8;
+        if notice_open {
+            if let Err(err) = group_sender.send(event_message(EventType::Open, id, id)) {
+                info!("send data channel err: {}", err);
+                return;
+            }
         }
+
+        buffer[0] = EventType::Struct as u8;
         for i in 0..4 {
-            buffer[i + 5] = (id >> (i * 8)) as u8;
-        }
-        if let Err(err) = group_sender.send(buffer[..9].to_vec()) {
-            info!("send data channel err: {}", err);
-            return;
+            buffer[i + 1] = (id >> (i * 8)) as u8;
         }
-
-        buffer[0] = 0; // typ: struct
         loop {
             let n = match d.read(&mut buffer[5..]).await {
                 Ok(n) => n,
@@ -223,6 +357,15 @@ impl PeerForwardInternal {
             }
 
             let is_broadcast = buffer[1..5] == buffer[5..9];
+            let permission = if is_broadcast {
+                Permission::Send
+            } else {
+                Permission::Unicast
+            };
+            if !roles.read().await.allows(id as i32, permission) {
+                debug!("user : {} may not {:?}, message dropped", id, permission);
+                continue;
+            }
             if is_broadcast {
                 //debug!("[rtc] send broadcast message");
                 if let Err(err) = group_sender.send(buffer[..n + 5].to_vec()) {
@@ -279,21 +422,60 @@ impl PeerForwardInternal {
 
 // publish
 impl PeerForwardInternal {
-    pub(crate) fn notice_network_event(&self, id: u32, open: bool) {
-        let mut buffer = vec![0u8; 9]; // typ (1) + from (0 ~ 3) + to (4 ~ 7)
-
-        buffer[0] = if open { 1 } else { 2 };
-        for i in 0..4 {
-            buffer[i + 1] = (id >> (i * 8)) as u8;
-        }
-        for i in 0..4 {
-            buffer[i + 5] = (id >> (i * 8)) as u8;
-        }
+    pub(crate) fn notice_network_event(&self, id: u32, typ: EventType) {
         let group_sender = self.data_channel_forward.sender.clone();
-        if let Err(err) = group_sender.send(buffer[..9].to_vec()) {
+        if let Err(err) = group_sender.send(event_message(typ, id, id)) {
             info!("send data channel err: {}", err);
+        }
+    }
+
+    /// Sends OnClose for the user once its connection has gone. With a resume
+    /// grace, the event is held back and dropped if the user opens a data
+    /// channel on this stream again in the meantime. Spectators are skipped
+    /// unless the room opted in.
+    pub(crate) async fn notice_close(self: Arc<Self>, id: u32, token: TokenDigest) {
+        if !self.roles.read().await.notices_events(id as i32) {
             return;
         }
+        if self.resume_grace == 0 {
+            self.notice_network_event(id, EventType::Close);
+            return;
+        }
+
+        let detached_at = Utc::now().timestamp_millis();
+        self.detached_map
+            .write()
+            .await
+            .insert(id, (token, detached_at));
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(self.resume_grace)).await;
+            let mut detached_map = self.detached_map.write().await;
+            if detached_map.get(&id).map(|(_, at)| *at) != Some(detached_at) {
+                return; // Resumed, or dropped again and handled by a later timer
+            }
+            detached_map.remove(&id);
+            drop(detached_map);
+            self.notice_network_event(id, EventType::Close);
+        });
+    }
+
+    /// Sends the held back OnClose of a user that left the room, so that
+    /// nobody taking the slot next resumes it.
+    pub(crate) async fn end_user_resume(&self, id: u32) {
+        if self.detached_map.write().await.remove(&id).is_some() {
+            self.notice_network_event(id, EventType::Close);
+        }
+    }
+
+    /// Unlike `notice_network_event`, this is written to every user sender
+    /// directly, so the user the event is about receives it as well.
+    pub(crate) async fn send_message_to_all(&self, message: Vec<u8>) {
+        let user_sender_map = self.user_sender_map.read().await;
+        for user_sender in user_sender_map.values() {
+            if let Err(err) = user_sender.send(message.clone()) {
+                info!("send data channel err: {}", err);
+            }
+        }
     }
 
     pub(crate) async fn publish_is_some(&self) -> bool {
@@ -308,7 +490,7 @@ impl PeerForwardInternal {
                 == RTCPeerConnectionState::Connected
     }
 
-    pub(crate) async fn set_publish(&self, peer: Arc<RTCPeerConnection>) -> Result<()> {
+    pub(crate) async fn set_publish(&self, id: u32, peer: Arc<RTCPeerConnection>) -> Result<()> {
         {
             let mut publish = self.publish.write().await;
             if publish.is_some() {
@@ -318,6 +500,7 @@ impl PeerForwardInternal {
             }
             let publish_peer = PublishRTCPeerConnection::new(
                 self.stream.clone(),
+                id,
                 peer.clone(),
                 self.publish_rtcp_channel.0.subscribe(),
             )
@@ -329,7 +512,7 @@ impl PeerForwardInternal {
             let mut publish_leave_time = self.publish_leave_time.write().await;
             *publish_leave_time = 0;
         }
-        self.send_event(ForwardEventType::PublishUp, get_peer_id(&peer))
+        self.send_event(ForwardEventType::PublishUp, get_peer_id(&peer), id)
             .await;
         Ok(())
     }
@@ -338,10 +521,10 @@ impl PeerForwardInternal {
         {
             let mut publish = self.publish.write().await;
             if publish.is_none() {
-                return Err(AppError::throw("publish is none"));
+                return Err(AppError::SessionNotFound);
             }
             if publish.as_ref().unwrap().id != get_peer_id(&peer) {
-                return Err(AppError::throw("publish not myself"));
+                return Err(AppError::permission_denied("publish not myself"));
             }
             *publish = None;
         }
@@ -360,7 +543,7 @@ impl PeerForwardInternal {
         user_sender_map.remove(&id);
         drop(user_sender_map);
 
-        self.send_event(ForwardEventType::PublishDown, get_peer_id(&peer))
+        self.send_event(ForwardEventType::PublishDown, get_peer_id(&peer), id)
             .await;
         Ok(())
     }
@@ -392,7 +575,7 @@ impl PeerForwardInternal {
         media_info: MediaInfo,
     ) -> Result<Arc<RTCPeerConnection>> {
         if media_info.video_transceiver.0 > 1 || media_info.audio_transceiver.0 > 1 {
-            return Err(AppError::throw("sendonly is more than 1"));
+            return Err(AppError::invalid_request("sendonly is more than 1"));
         }
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
@@ -492,6 +675,7 @@ impl PeerForwardInternal {
         &self,
         _peer: Arc<RTCPeerConnection>,
         id: u32,
+        token: TokenDigest,
         dc: Arc<RTCDataChannel>,
     ) -> Result<()> {
         let group_sender = self.data_channel_forward.sender.clone();
@@ -503,10 +687,14 @@ impl PeerForwardInternal {
 
         Self::data_channel_forward(
             id,
+            token,
             dc,
             group_sender,
             user_sender,
             self.user_sender_map.clone(),
+            self.properties.clone(),
+            self.roles.clone(),
+            self.detached_map.clone(),
         )
         .await;
 
@@ -518,13 +706,14 @@ impl PeerForwardInternal {
 impl PeerForwardInternal {
     pub(crate) async fn new_subscription_peer(
         &self,
+        id: u32,
         media_info: MediaInfo,
     ) -> Result<Arc<RTCPeerConnection>> {
         if !self.publish_is_some().await {
-            return Err(AppError::throw("publish is none"));
+            return Err(AppError::conflict("publish is none"));
         }
         if media_info.video_transceiver.1 > 1 || media_info.audio_transceiver.1 > 1 {
-            return Err(AppError::throw("recvonly is more than 1"));
+            return Err(AppError::invalid_request("recvonly is more than 1"));
         }
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
@@ -545,6 +734,7 @@ impl PeerForwardInternal {
         {
             let s = SubscribeRTCPeerConnection::new(
                 self.stream.clone(),
+                id,
                 peer.clone(),
                 self.publish_rtcp_channel.0.clone(),
                 (
@@ -562,7 +752,7 @@ impl PeerForwardInternal {
             self.subscribe_group.write().await.push(s);
             *self.subscribe_leave_time.write().await = 0;
         }
-        self.send_event(ForwardEventType::SubscribeUp, get_peer_id(&peer))
+        self.send_event(ForwardEventType::SubscribeUp, get_peer_id(&peer), id)
             .await;
 
         Ok(peer)
@@ -645,6 +835,18 @@ impl PeerForwardInternal {
         })
     }
 
+    /// Closes the subscribe sessions owned by the user. The state change
+    /// handler then runs `remove_subscribe` for each of them.
+    pub(crate) async fn close_user_subscribes(&self, id: u32) -> Result<()> {
+        let subscribe_group = self.subscribe_group.read().await;
+        for subscribe in subscribe_group.iter() {
+            if subscribe.user == id {
+                subscribe.peer.close().await?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn remove_subscribe(&self, id: u32, peer: Arc<RTCPeerConnection>) -> Result<()> {
         let mut flag = false;
         let session = get_peer_id(&peer);
@@ -663,7 +865,7 @@ impl PeerForwardInternal {
             }
         }
         if flag {
-            self.send_event(ForwardEventType::SubscribeDown, get_peer_id(&peer))
+            self.send_event(ForwardEventType::SubscribeDown, get_peer_id(&peer), id)
                 .await;
 
             let mut user_sender_map = self.user_sender_map.write().await;
@@ -672,7 +874,7 @@ impl PeerForwardInternal {
 
             Ok(())
         } else {
-            Err(AppError::throw("not found session"))
+            Err(AppError::SessionNotFound)
         }
     }
 
@@ -680,6 +882,7 @@ impl PeerForwardInternal {
         &self,
         _peer: Arc<RTCPeerConnection>,
         id: u32,
+        token: TokenDigest,
         dc: Arc<RTCDataChannel>,
     ) -> Result<()> {
         let group_sender = self.data_channel_forward.sender.clone();
@@ -691,10 +894,14 @@ impl PeerForwardInternal {
 
         Self::data_channel_forward(
             id,
+            token,
             dc,
             group_sender,
             user_sender,
             self.user_sender_map.clone(),
+            self.properties.clone(),
+            self.roles.clone(),
+            self.detached_map.clone(),
         )
         .await;
 
@@ -707,11 +914,18 @@ impl PeerForwardInternal {
     //     publish.as_ref().map(|p| p.peer.clone())
     // }
 
-    async fn send_event(&self, r#type: ForwardEventType, session: String) {
-        let _ = self.event_sender.send(ForwardEvent {
+    async fn send_event(&self, r#type: ForwardEventType, session: String, user: u32) {
+        let event = ForwardEvent {
             r#type,
             session,
+            user,
             stream_info: self.info().await,
-        });
+        };
+        let room_event = event.clone().into();
+        match self.held_events.lock().unwrap().as_mut() {
+            Some(held_events) => held_events.push(room_event),
+            None => self.events.send(room_event),
+        }
+        let _ = self.event_sender.send(event);
     }
 }
@@ -25,6 +25,8 @@ pub struct SessionInfo {
 pub struct ForwardEvent {
     pub r#type: ForwardEventType,
     pub session: String,
+    /// User owning the session.
+    pub user: u32,
     pub stream_info: ForwardInfo,
 }
 
@@ -2,7 +2,7 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 use tracing::info;
 
 use webrtc::ice_transport::ice_gatherer::OnLocalCandidateHdlrFn;
@@ -17,7 +17,11 @@ use message::{ForwardInfo, Layer};
 use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 
 use crate::error::AppError;
+use crate::event::RoomEvents;
+use crate::forward::rtc::client::TokenDigest;
+use crate::property::RoomProperties;
 use crate::result::Result;
+use crate::role::RoomRoles;
 
 pub mod client;
 pub mod internal;
@@ -38,10 +42,24 @@ pub type OnPeerConnectionEvtHdlrFn =
     Box<dyn (FnMut() -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>) + Send + Sync>;
 
 impl PeerForward {
-    pub fn new(stream: impl ToString, ice_server: Vec<RTCIceServer>) -> Self {
+    pub fn new(
+        stream: impl ToString,
+        ice_server: Vec<RTCIceServer>,
+        properties: Arc<RwLock<RoomProperties>>,
+        roles: Arc<RwLock<RoomRoles>>,
+        resume_grace: u64,
+        events: Arc<RoomEvents>,
+    ) -> Self {
         PeerForward {
             publish_lock: Arc::new(Mutex::new(())),
-            internal: Arc::new(PeerForwardInternal::new(stream, ice_server)),
+            internal: Arc::new(PeerForwardInternal::new(
+                stream,
+                ice_server,
+                properties,
+                roles,
+                resume_grace,
+                events,
+            )),
         }
     }
 
@@ -81,6 +99,7 @@ impl PeerForward {
     pub async fn set_publish(
         &self,
         id: u32,
+        token: TokenDigest,
         offer: RTCSessionDescription,
         on_ice_candidate: OnLocalCandidateHdlrFn,
         on_peer_connected: OnPeerConnectionEvtHdlrFn,
@@ -124,7 +143,7 @@ impl PeerForward {
                             let _ = pc.close().await;
                         }
                         RTCPeerConnectionState::Closed => {
-                            internal.notice_network_event(id.clone(), false);
+                            internal.clone().notice_close(id, token).await;
                             let _ = internal.remove_publish(id.clone(), pc).await;
                         }
                         _ => {}
@@ -148,13 +167,13 @@ impl PeerForward {
         peer.on_data_channel(Box::new(move |dc| {
             if let (Some(internal), Some(pc)) = (internal.upgrade(), pc.upgrade()) {
                 tokio::spawn(async move {
-                    let _ = internal.publish_data_channel(pc, id, dc).await;
+                    let _ = internal.publish_data_channel(pc, id, token, dc).await;
                 });
             }
             Box::pin(async {})
         }));
         let description = peer_complete(offer, peer.clone()).await?;
-        self.internal.set_publish(peer.clone()).await?;
+        self.internal.set_publish(id, peer.clone()).await?;
         let session = get_peer_id(&peer);
         Ok((peer, description, session))
     }
@@ -166,16 +185,17 @@ impl PeerForward {
     pub async fn add_subscribe(
         &self,
         id: u32,
+        token: TokenDigest,
         offer: RTCSessionDescription,
         on_ice_candidate: OnLocalCandidateHdlrFn,
         on_peer_connected: OnPeerConnectionEvtHdlrFn,
     ) -> Result<(Arc<RTCPeerConnection>, RTCSessionDescription, String)> {
         if !self.internal.publish_is_ok().await {
-            return Err(AppError::throw("publish is not ok"));
+            return Err(AppError::conflict("publish is not ok"));
         }
         let peer = self
             .internal
-            .new_subscription_peer(MediaInfo::try_from(offer.unmarshal()?)?)
+            .new_subscription_peer(id, MediaInfo::try_from(offer.unmarshal()?)?)
             .await?;
         let internal = Arc::downgrade(&self.internal);
         let pc = Arc::downgrade(&peer);
@@ -201,7 +221,7 @@ impl PeerForward {
                             let _ = pc.close().await;
                         }
                         RTCPeerConnectionState::Closed => {
-                            internal.notice_network_event(id.clone(), false);
+                            internal.clone().notice_close(id, token).await;
                             let _ = internal.remove_subscribe(id.clone(), pc).await;
                         }
                         _ => {}
@@ -215,7 +235,7 @@ impl PeerForward {
         peer.on_data_channel(Box::new(move |dc| {
             if let (Some(internal), Some(pc)) = (internal.upgrade(), pc.upgrade()) {
                 tokio::spawn(async move {
-                    let _ = internal.subscribe_data_channel(pc, id, dc).await;
+                    let _ = internal.subscribe_data_channel(pc, id, token, dc).await;
                 });
             }
             Box::pin(async {})
@@ -237,7 +257,7 @@ impl PeerForward {
             }
             Ok(layers)
         } else {
-            Err(AppError::throw("not layers"))
+            Err(AppError::conflict("not layers"))
         }
     }
 
@@ -257,11 +277,39 @@ impl PeerForward {
     //     self.internal.remove_peer(session).await
     // }
 
+    pub async fn get_peer(&self, session: String) -> Option<Arc<RTCPeerConnection>> {
+        self.internal.get_peer(session).await
+    }
+
+    pub async fn session_user(&self, session: String) -> Option<u32> {
+        self.internal.session_user(session).await
+    }
+
+    pub async fn remove_session(&self, session: String) -> Result<()> {
+        self.internal.remove_session(session).await
+    }
+
+    pub async fn close_user_subscribes(&self, id: u32) -> Result<()> {
+        self.internal.close_user_subscribes(id).await
+    }
+
+    pub async fn send_message_to_all(&self, message: Vec<u8>) {
+        self.internal.send_message_to_all(message).await;
+    }
+
     pub async fn close(&self) -> Result<()> {
         self.internal.close().await?;
         Ok(())
     }
 
+    pub fn announce(&self) {
+        self.internal.announce()
+    }
+
+    pub async fn end_user_resume(&self, id: u32) {
+        self.internal.end_user_resume(id).await
+    }
+
     pub async fn info(&self) -> ForwardInfo {
         self.internal.info().await
     }
@@ -14,6 +14,7 @@ use super::media::MediaInfo;
 
 pub(crate) struct PublishRTCPeerConnection {
     pub(crate) id: String,
+    pub(crate) user: u32,
     pub(crate) peer: Arc<RTCPeerConnection>,
     pub(crate) media_info: MediaInfo,
     pub(crate) create_time: i64,
@@ -22,6 +23,7 @@ pub(crate) struct PublishRTCPeerConnection {
 impl PublishRTCPeerConnection {
     pub(crate) async fn new(
         path: String,
+        user: u32,
         peer: Arc<RTCPeerConnection>,
         rtcp_recv: broadcast::Receiver<(RtcpMessage, u32)>,
     ) -> Result<Self> {
@@ -36,6 +38,7 @@ impl PublishRTCPeerConnection {
         tokio::spawn(Self::peer_send_rtcp(path, id.clone(), peer_weak, rtcp_recv));
         Ok(Self {
             id,
+            user,
             peer,
             media_info,
             create_time: Utc::now().timestamp_millis(),
@@ -30,6 +30,7 @@ struct SubscribeForwardChannel {
 
 pub(crate) struct SubscribeRTCPeerConnection {
     pub(crate) id: String,
+    pub(crate) user: u32,
     pub(crate) peer: Arc<RTCPeerConnection>,
     pub(crate) create_time: i64,
     select_layer_sender: broadcast::Sender<SelectLayerBody>,
@@ -38,6 +39,7 @@ pub(crate) struct SubscribeRTCPeerConnection {
 impl SubscribeRTCPeerConnection {
     pub(crate) async fn new(
         stream: String,
+        user: u32,
         peer: Arc<RTCPeerConnection>,
         publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
         (publish_tracks, publish_track_change): (
@@ -81,6 +83,7 @@ impl SubscribeRTCPeerConnection {
         let _ = publish_track_change.send(());
         Self {
             id,
+            user,
             peer,
             create_time: Utc::now().timestamp_millis(),
             select_layer_sender,
@@ -96,8 +99,9 @@ impl SubscribeRTCPeerConnection {
     }
 
     pub(crate) fn select_kind_rid(&self, kind: RTPCodecType, rid: String) -> Result<()> {
-        if let Err(err) = self.select_layer_sender.send((kind, rid)) {
-            Err(AppError::throw(format!("select layer send err: {}", err)))
+        // Nothing receives once the session is closed
+        if self.select_layer_sender.send((kind, rid)).is_err() {
+            Err(AppError::SessionNotFound)
         } else {
             Ok(())
         }
@@ -0,0 +1,170 @@
+use std::sync::{Arc, Mutex};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// A room key as it is stored: an argon2id PHC string with its own salt.
+/// Rooms persisted before salted hashing carry a 32-bit FNV hash, which is
+/// still accepted so they keep working after an upgrade.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyHash {
+    Argon2(String),
+    Legacy(u32),
+}
+
+impl KeyHash {
+    pub fn new(key: &str) -> Self {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).unwrap();
+        let hash = Argon2::default()
+            .hash_password(key.as_bytes(), &salt)
+            .unwrap();
+        KeyHash::Argon2(hash.to_string())
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, KeyHash::Legacy(_))
+    }
+
+    fn verify(&self, key: &str) -> bool {
+        match self {
+            KeyHash::Argon2(hash) => match PasswordHash::new(hash) {
+                // The output comparison of verify_password is constant time
+                Ok(hash) => Argon2::default()
+                    .verify_password(key.as_bytes(), &hash)
+                    .is_ok(),
+                Err(_) => false,
+            },
+            KeyHash::Legacy(hash) => {
+                let key_hash = utils::unique::hash_from_string(key.to_string());
+                key_hash.to_le_bytes().ct_eq(&hash.to_le_bytes()).into()
+            }
+        }
+    }
+}
+
+/// A room key and the digest of the last key that matched it. Every client
+/// request is authenticated with the shared key, so matching keys are
+/// checked against the digest instead of running argon2 each time. Wrong
+/// keys always go through argon2.
+#[derive(Debug, Clone)]
+pub struct RoomKey {
+    hash: KeyHash,
+    verified: Arc<Mutex<Option<[u8; 32]>>>,
+}
+
+impl RoomKey {
+    pub fn new(key: &str) -> Self {
+        Self::from_hash(KeyHash::new(key))
+    }
+
+    /// `new` on the blocking pool, hashing takes as long as `verify`.
+    pub async fn new_blocking(key: String) -> Self {
+        tokio::task::spawn_blocking(move || Self::new(&key))
+            .await
+            .unwrap()
+    }
+
+    pub fn from_hash(hash: KeyHash) -> Self {
+        Self {
+            hash,
+            verified: Default::default(),
+        }
+    }
+
+    pub fn hash(&self) -> KeyHash {
+        self.hash.clone()
+    }
+
+    pub fn verify(&self, key: &str) -> bool {
+        let digest: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        if let Some(verified) = *self.verified.lock().unwrap() {
+            if bool::from(verified.ct_eq(&digest)) {
+                return true;
+            }
+        }
+        if !self.hash.verify(key) {
+            return false;
+        }
+        *self.verified.lock().unwrap() = Some(digest);
+        true
+    }
+
+    /// `verify` on the blocking pool, argon2 would stall the runtime thread.
+    pub async fn verify_blocking(&self, key: String) -> bool {
+        let room_key = self.clone();
+        tokio::task::spawn_blocking(move || room_key.verify(&key))
+            .await
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_salted_and_verifies_only_the_key() {
+        let hash = KeyHash::new("secret");
+        assert!(matches!(&hash, KeyHash::Argon2(phc) if phc.starts_with("$argon2id$")));
+        assert!(hash.verify("secret"));
+        assert!(!hash.verify("Secret"));
+        assert!(!hash.verify(""));
+
+        let other = KeyHash::new("secret");
+        assert_ne!(
+            serde_json::to_string(&hash).unwrap(),
+            serde_json::to_string(&other).unwrap()
+        );
+    }
+
+    #[test]
+    fn legacy_hash_still_verifies() {
+        let hash = KeyHash::Legacy(utils::unique::hash_from_string("secret".to_string()));
+        assert!(hash.is_legacy());
+        assert!(hash.verify("secret"));
+        assert!(!hash.verify("other"));
+
+        // Snapshots written before argon2 hold a plain number
+        let json = serde_json::to_string(&hash).unwrap();
+        let hash: KeyHash = serde_json::from_str(&json).unwrap();
+        assert!(hash.is_legacy());
+        assert!(!KeyHash::new("secret").is_legacy());
+    }
+
+    #[test]
+    fn digest_cache_keeps_the_last_match_only() {
+        let room_key = RoomKey::new("secret");
+        assert!(room_key.verified.lock().unwrap().is_none());
+
+        assert!(!room_key.verify("wrong"));
+        assert!(room_key.verified.lock().unwrap().is_none());
+
+        assert!(room_key.verify("secret"));
+        let digest: [u8; 32] = Sha256::digest(b"secret").into();
+        assert_eq!(*room_key.verified.lock().unwrap(), Some(digest));
+
+        // Clones share the cache, and a cached match does not let others in
+        let clone = room_key.clone();
+        assert!(clone.verify("secret"));
+        assert!(!clone.verify("wrong"));
+        assert_eq!(*room_key.verified.lock().unwrap(), Some(digest));
+    }
+
+    #[test]
+    fn from_hash_verifies_a_stored_key() {
+        let room_key = RoomKey::from_hash(RoomKey::new("secret").hash());
+        assert!(room_key.verify("secret"));
+        assert!(!room_key.verify("wrong"));
+    }
+
+    #[tokio::test]
+    async fn blocking_variants_match_sync_ones() {
+        let room_key = RoomKey::new_blocking("secret".to_string()).await;
+        assert!(room_key.verify_blocking("secret".to_string()).await);
+        assert!(!room_key.verify_blocking("wrong".to_string()).await);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use tracing::warn;
+
+use crate::config;
+use crate::metrics;
+
+lazy_static! {
+    static ref LOCKOUT: Mutex<Lockout> = Mutex::new(Lockout::default());
+}
+
+pub fn init(config: config::Lockout) {
+    LOCKOUT.lock().unwrap().config = config;
+}
+
+/// Milliseconds until the remote ip or the room may check credentials
+/// again, or `None` if neither is locked out.
+pub fn retry_after(remote_ip: IpAddr, room_id: i32) -> Option<i64> {
+    ip_retry_after(remote_ip).max(room_retry_after(room_id))
+}
+
+/// `retry_after` of the remote ip alone.
+pub fn ip_retry_after(remote_ip: IpAddr) -> Option<i64> {
+    let lockout = LOCKOUT.lock().unwrap();
+    remaining(lockout.ips.get(&remote_ip))
+}
+
+/// `retry_after` of the room alone.
+pub fn room_retry_after(room_id: i32) -> Option<i64> {
+    let lockout = LOCKOUT.lock().unwrap();
+    remaining(lockout.rooms.get(&room_id))
+}
+
+fn remaining(attempts: Option<&Attempts>) -> Option<i64> {
+    let now = Utc::now().timestamp_millis();
+    let locked_until = attempts?.locked_until;
+    if locked_until > now {
+        Some(locked_until - now)
+    } else {
+        None
+    }
+}
+
+/// Records the outcome of a shared key, master key or token check. A success
+/// clears the failures of the ip, the room keeps counting until its window
+/// passes.
+pub fn record(remote_ip: IpAddr, room_id: i32, is_ok: bool) {
+    let now = Utc::now().timestamp_millis();
+    LOCKOUT
+        .lock()
+        .unwrap()
+        .record(remote_ip, room_id, is_ok, now);
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: i64,
+    locked_until: i64,
+}
+
+#[derive(Default)]
+struct Lockout {
+    config: config::Lockout,
+    ips: HashMap<IpAddr, Attempts>,
+    rooms: HashMap<i32, Attempts>,
+}
+
+impl Lockout {
+    fn record(&mut self, remote_ip: IpAddr, room_id: i32, is_ok: bool, now: i64) {
+        if is_ok {
+            self.ips.remove(&remote_ip);
+            return;
+        }
+
+        let config = self.config.clone();
+        if let Some((failures, delay)) =
+            Lockout::fail(&mut self.ips, remote_ip, config.max_failures, &config, now)
+        {
+            metrics::LOCKOUTS.inc();
+            warn!(
+                "[audit] lockout ip : {}, room : {}, failures : {}, for {} ms",
+                remote_ip, room_id, failures, delay
+            );
+        }
+        if let Some((failures, delay)) = Lockout::fail(
+            &mut self.rooms,
+            room_id,
+            config.room_max_failures,
+            &config,
+            now,
+        ) {
+            metrics::LOCKOUTS.inc();
+            warn!(
+                "[audit] lockout room : {}, last ip : {}, failures : {}, for {} ms",
+                room_id, remote_ip, failures, delay
+            );
+        }
+    }
+
+    /// Counts a failure for the key. Once `max_failures` failures happened
+    /// within the window, the key is locked for `base_delay`, doubled for
+    /// every failure after that up to `max_delay`. Returns the failures and
+    /// the delay when this failure started a lockout.
+    fn fail<K: Hash + Eq>(
+        map: &mut HashMap<K, Attempts>,
+        key: K,
+        max_failures: u32,
+        config: &config::Lockout,
+        now: i64,
+    ) -> Option<(u32, i64)> {
+        if max_failures == 0 {
+            return None;
+        }
+        // Forget keys that have been quiet for a whole window
+        if map.len() > 1024 {
+            map.retain(|_, attempts| {
+                attempts.locked_until > now || now - attempts.last_failure < config.window as i64
+            });
+        }
+
+        let attempts = map.entry(key).or_default();
+        if now - attempts.last_failure >= config.window as i64 {
+            attempts.failures = 0;
+        }
+        attempts.failures += 1;
+        attempts.last_failure = now;
+        if attempts.failures < max_failures {
+            return None;
+        }
+
+        let doublings = (attempts.failures - max_failures).min(32);
+        let delay = config
+            .base_delay
+            .saturating_mul(1u64 << doublings)
+            .min(config.max_delay) as i64;
+        attempts.locked_until = now + delay;
+        Some((attempts.failures, delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED);
+
+    fn lockout() -> Lockout {
+        Lockout {
+            config: config::Lockout {
+                max_failures: 3,
+                room_max_failures: 4,
+                base_delay: 1000,
+                max_delay: 5000,
+                window: 10000,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn fail_ip(lockout: &mut Lockout, now: i64) -> Option<(u32, i64)> {
+        let config = lockout.config.clone();
+        Lockout::fail(&mut lockout.ips, IP, config.max_failures, &config, now)
+    }
+
+    #[test]
+    fn back_off_doubles_up_to_max_delay() {
+        let mut lockout = lockout();
+        assert_eq!(fail_ip(&mut lockout, 0), None);
+        assert_eq!(fail_ip(&mut lockout, 1), None);
+        assert_eq!(fail_ip(&mut lockout, 2), Some((3, 1000)));
+        assert_eq!(lockout.ips[&IP].locked_until, 1002);
+        assert_eq!(fail_ip(&mut lockout, 3), Some((4, 2000)));
+        assert_eq!(fail_ip(&mut lockout, 4), Some((5, 4000)));
+        assert_eq!(fail_ip(&mut lockout, 5), Some((6, 5000)));
+        assert_eq!(lockout.ips[&IP].locked_until, 5005);
+    }
+
+    #[test]
+    fn window_resets_failures() {
+        let mut lockout = lockout();
+        fail_ip(&mut lockout, 0);
+        fail_ip(&mut lockout, 1);
+        // The window counts from the last failure
+        assert_eq!(fail_ip(&mut lockout, 10001), None);
+        assert_eq!(lockout.ips[&IP].failures, 1);
+    }
+
+    #[test]
+    fn zero_max_failures_disables() {
+        let mut lockout = lockout();
+        let config = lockout.config.clone();
+        for now in 0..10 {
+            assert_eq!(Lockout::fail(&mut lockout.ips, IP, 0, &config, now), None);
+        }
+        assert!(lockout.ips.is_empty());
+    }
+
+    #[test]
+    fn success_clears_the_ip_but_not_the_room() {
+        let mut lockout = lockout();
+        lockout.record(IP, 1, false, 0);
+        lockout.record(IP, 1, false, 1);
+        lockout.record(IP, 1, true, 2);
+        assert!(!lockout.ips.contains_key(&IP));
+        assert_eq!(lockout.rooms[&1].failures, 2);
+    }
+
+    #[test]
+    fn room_counts_failures_of_every_ip() {
+        let mut lockout = lockout();
+        lockout.record(IP, 1, false, 0);
+        lockout.record(IP, 1, false, 1);
+        lockout.record(OTHER_IP, 1, false, 2);
+        assert_eq!(lockout.rooms[&1].locked_until, 0);
+        lockout.record(OTHER_IP, 1, false, 3);
+        assert_eq!(lockout.rooms[&1].locked_until, 1003);
+        // Neither ip reached its own limit
+        assert_eq!(lockout.ips[&IP].locked_until, 0);
+        assert_eq!(lockout.ips[&OTHER_IP].locked_until, 0);
+    }
+}
@@ -3,7 +3,7 @@ extern crate lazy_static;
 
 use axum::body::{Body, Bytes};
 use axum::extract::Request;
-use axum::http::{HeaderMap, StatusCode};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use axum::Router;
@@ -14,6 +14,8 @@ use room::Room;
 use route::r#static::static_server;
 use std::collections::HashMap;
 use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
@@ -24,20 +26,29 @@ use crate::auth::ManyValidate;
 use crate::config::Config;
 use crate::result::Result;
 use crate::route::AppState;
+use crate::store::{RoomStore, StoreWriter};
+use crate::webhook::AuthWebhook;
 
 mod auth;
 mod config;
 mod constant;
 mod error;
+mod event;
 mod forward;
 mod http;
+mod key;
+mod lockout;
 mod r#macro;
 mod metrics;
+mod property;
 mod result;
+mod role;
 mod room;
 mod route;
 mod rtc;
+mod store;
 mod support;
+mod webhook;
 
 pub const HASH_LEN: usize = 8;
 
@@ -49,12 +60,29 @@ async fn print_request_response(
     req: Request,
     next: Next,
 ) -> std::result::Result<impl IntoResponse, (StatusCode, String)> {
-    let req_headers = req.headers().clone();
+    let mut req_headers = req.headers().clone();
+    // Credentials are printed as "Sensitive"
+    let names = route::CREDENTIAL_HEADERS.iter().map(|(name, _)| *name);
+    for name in names.chain([header::AUTHORIZATION.as_str()]) {
+        if let Some(value) = req_headers.get_mut(name) {
+            value.set_sensitive(true);
+        }
+    }
     let (parts, body) = req.into_parts();
     let bytes = buffer_and_print("request", req_headers, body).await?;
     let req = Request::from_parts(parts, Body::from(bytes));
 
     let res = next.run(req).await;
+    // Event streams never end, so they are not buffered
+    if res.headers().get(header::CONTENT_TYPE)
+        == Some(&header::HeaderValue::from_static("text/event-stream"))
+    {
+        debug!(
+            "response headers = {:?} body = <event stream>",
+            res.headers()
+        );
+        return Ok(res);
+    }
     let res_headers = res.headers().clone();
     let (parts, body) = res.into_parts();
     let bytes = buffer_and_print("response", res_headers, body).await?;
@@ -89,6 +117,21 @@ where
     Ok(bytes)
 }
 
+async fn restore_rooms(cfg: &Config, store: &dyn RoomStore) {
+    let snapshots = match store.load() {
+        Ok(snapshots) => snapshots,
+        Err(err) => {
+            error!("failed to load rooms from store: {}", err);
+            return;
+        }
+    };
+    let mut rooms = ROOMS.lock().await;
+    for snapshot in snapshots {
+        info!("restore room : {} ({})", snapshot.id, snapshot.name);
+        rooms.insert(snapshot.id, Room::from_snapshot(snapshot, cfg.clone()));
+    }
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Args {
@@ -110,8 +153,32 @@ async fn main() -> Result<()> {
     let addr = listener.local_addr().unwrap();
     info!("Server listening on {}", addr);
     debug!("Debug tool shortcut http://localhost:{}", addr.port());
+    let store = store::from_config(&cfg.room).expect("room store open error");
+    restore_rooms(&cfg, store.as_ref()).await;
+    let store = StoreWriter::spawn(store);
+    metrics::init();
+    lockout::init(cfg.auth.lockout.clone());
+    tokio::spawn(room::idle_check_tick(
+        store.clone(),
+        cfg.room.idle_timeout.0,
+    ));
+    let webhook = cfg
+        .auth
+        .webhook
+        .clone()
+        .map(|webhook| Arc::new(AuthWebhook::new(webhook)));
     let app_state = AppState {
         config: cfg.clone(),
+        store,
+        webhook,
+    };
+    // Without [auth] every request is let through, which must not open the
+    // operator routes to anyone.
+    let admin_route = if cfg.auth.has_server_admin() {
+        route::admin::route()
+    } else {
+        warn!("admin routes disabled, no server_admin credential or jwt configured");
+        Router::new()
     };
     let auth_layer = ValidateRequestHeaderLayer::custom(ManyValidate::new(vec![cfg.auth]));
     let app = Router::new()
@@ -119,15 +186,28 @@ async fn main() -> Result<()> {
             route::room::room::route()
                 .merge(route::room::create::route())
                 .merge(route::room::delete::route())
+                .merge(route::room::events::route())
                 .merge(route::room::join::route())
+                .merge(route::room::quick_join::route())
+                .merge(route::room::invite::route())
                 .merge(route::room::exit::route())
+                .merge(route::room::kick::route())
+                .merge(route::room::property::route())
+                .merge(route::room::roster::route())
+                .merge(route::room::role::route())
+                .merge(route::room::token::route())
                 .merge(route::rtc::infos::route())
                 .merge(route::rtc::stream::route())
                 .merge(route::rtc::whip::route())
                 .merge(route::rtc::whep::route())
+                .merge(route::rtc::session::route())
                 .merge(route::ws::route())
+                .merge(route::metrics::route())
+                .merge(admin_route)
                 .layer(auth_layer),
         )
+        .merge(route::health::route())
+        .merge(route::openapi::route())
         .with_state(app_state.clone())
         .layer(if cfg.http.cors {
             CorsLayer::permissive()
@@ -148,7 +228,11 @@ async fn main() -> Result<()> {
             }),
         );
     tokio::select! {
-        Err(e) = axum::serve(listener, static_server(app)).into_future() => error!("Application error: {e}"),
+        Err(e) = axum::serve(
+            listener,
+            static_server(app).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .into_future() => error!("Application error: {e}"),
         msg = signal::wait_for_stop_signal() => debug!("Received signal: {}", msg),
     }
     info!("Server shutdown");
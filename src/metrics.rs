@@ -1,8 +1,20 @@
 use lazy_static::lazy_static;
-use prometheus::{Registry, TextEncoder};
+use prometheus::{IntCounter, Registry, TextEncoder};
 
 lazy_static! {
     pub static ref REGISTRY: Registry =
-        Registry::new_custom(Some("rust-server-for-multiplayer".to_string()), None).unwrap();
+        Registry::new_custom(Some("rust_server_for_multiplayer".to_string()), None).unwrap();
     pub static ref ENCODER: TextEncoder = TextEncoder::new();
+    pub static ref EXPIRED_ROOMS: IntCounter =
+        IntCounter::new("expired_rooms", "rooms removed after the idle timeout").unwrap();
+    pub static ref LOCKOUTS: IntCounter = IntCounter::new(
+        "lockouts",
+        "remote ips and rooms locked out after failed credential checks"
+    )
+    .unwrap();
+}
+
+pub fn init() {
+    REGISTRY.register(Box::new(EXPIRED_ROOMS.clone())).unwrap();
+    REGISTRY.register(Box::new(LOCKOUTS.clone())).unwrap();
 }
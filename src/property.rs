@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use libws::{event_message, EventType};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::room::NO_HOST;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Property {
+    pub value: Value,
+    pub version: u64,
+    #[serde(default)]
+    pub host_only: bool,
+}
+
+/// A change requested for one key. A `null` value removes the key.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PropertyUpdate {
+    #[serde(default)]
+    pub value: Option<Value>,
+    /// Expected current version of the key, 0 if it must not exist yet.
+    #[serde(default)]
+    pub version: Option<u64>,
+    #[serde(default)]
+    pub host_only: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct PropertiesEventJson<'a> {
+    full: bool,
+    properties: &'a HashMap<String, Option<Property>>,
+}
+
+/// Builds a Properties event. `full` marks the complete set sent to a
+/// connection that has just been opened, otherwise only changed keys are
+/// listed and removed keys are `null`. `from` is the user who made the
+/// change, or `NO_HOST` when it came from the server.
+pub fn properties_message(
+    from: u32,
+    full: bool,
+    properties: &HashMap<String, Option<Property>>,
+) -> Vec<u8> {
+    let payload = serde_json::to_vec(&PropertiesEventJson { full, properties }).unwrap();
+    [event_message(EventType::Properties, from, from), payload].concat()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoomProperties {
+    map: HashMap<String, Property>,
+}
+
+impl RoomProperties {
+    pub fn new(map: HashMap<String, Property>) -> Self {
+        Self { map }
+    }
+
+    pub fn all(&self) -> HashMap<String, Property> {
+        self.map.clone()
+    }
+
+    pub fn full_message(&self) -> Vec<u8> {
+        let properties = self
+            .map
+            .iter()
+            .map(|(key, property)| (key.clone(), Some(property.clone())))
+            .collect();
+        properties_message(NO_HOST as u32, true, &properties)
+    }
+
+    /// Applies every update or none of them. Returns the changed keys, or
+    /// `None` when a version check failed or a host-only key was touched by
+    /// someone who is not the host.
+    pub fn apply(
+        &mut self,
+        updates: HashMap<String, PropertyUpdate>,
+        is_host: bool,
+    ) -> Option<HashMap<String, Option<Property>>> {
+        for (key, update) in updates.iter() {
+            let current = self.map.get(key);
+            let current_version = current.map(|property| property.version).unwrap_or(0);
+            if update
+                .version
+                .is_some_and(|version| version != current_version)
+            {
+                return None;
+            }
+            let host_only = current.is_some_and(|property| property.host_only)
+                || update.host_only.unwrap_or(false);
+            if host_only && !is_host {
+                return None;
+            }
+        }
+
+        let mut changed = HashMap::new();
+        for (key, update) in updates.into_iter() {
+            match update.value {
+                Some(value) => {
+                    let current = self.map.get(&key);
+                    let property = Property {
+                        value,
+                        version: current.map(|property| property.version).unwrap_or(0) + 1,
+                        host_only: update
+                            .host_only
+                            .unwrap_or(current.is_some_and(|property| property.host_only)),
+                    };
+                    self.map.insert(key.clone(), property.clone());
+                    changed.insert(key, Some(property));
+                }
+                None => {
+                    if self.map.remove(&key).is_some() {
+                        changed.insert(key, None);
+                    }
+                }
+            }
+        }
+        Some(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(value: Option<Value>, version: Option<u64>) -> PropertyUpdate {
+        PropertyUpdate {
+            value,
+            version,
+            host_only: None,
+        }
+    }
+
+    fn updates(list: Vec<(&str, PropertyUpdate)>) -> HashMap<String, PropertyUpdate> {
+        list.into_iter()
+            .map(|(key, update)| (key.to_string(), update))
+            .collect()
+    }
+
+    fn set(key: &str, value: Value, version: Option<u64>) -> HashMap<String, PropertyUpdate> {
+        updates(vec![(key, update(Some(value), version))])
+    }
+
+    #[test]
+    fn versions_count_up_from_zero() {
+        let mut properties = RoomProperties::default();
+        let changed = properties
+            .apply(set("map", json!("a"), Some(0)), false)
+            .unwrap();
+        assert_eq!(changed["map"].as_ref().unwrap().version, 1);
+
+        // 0 means the key must not exist yet
+        assert!(properties
+            .apply(set("map", json!("b"), Some(0)), false)
+            .is_none());
+
+        let changed = properties
+            .apply(set("map", json!("b"), Some(1)), false)
+            .unwrap();
+        let property = changed["map"].as_ref().unwrap();
+        assert_eq!((property.value.clone(), property.version), (json!("b"), 2));
+    }
+
+    #[test]
+    fn failed_check_applies_nothing() {
+        let mut properties = RoomProperties::default();
+        properties.apply(set("a", json!(1), None), false).unwrap();
+
+        let batch = updates(vec![
+            ("a", update(Some(json!(2)), Some(5))),
+            ("b", update(Some(json!(2)), None)),
+        ]);
+        assert!(properties.apply(batch, false).is_none());
+        let all = properties.all();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all["a"].value, json!(1));
+    }
+
+    #[test]
+    fn update_without_version_always_applies() {
+        let mut properties = RoomProperties::default();
+        for value in 1..=3 {
+            properties
+                .apply(set("a", json!(value), None), false)
+                .unwrap();
+        }
+        assert_eq!(properties.all()["a"].value, json!(3));
+        assert_eq!(properties.all()["a"].version, 3);
+    }
+
+    #[test]
+    fn null_value_removes_the_key() {
+        let mut properties = RoomProperties::default();
+        properties.apply(set("a", json!(1), None), false).unwrap();
+
+        let changed = properties
+            .apply(updates(vec![("a", update(None, Some(1)))]), false)
+            .unwrap();
+        assert_eq!(changed["a"], None);
+        assert!(properties.all().is_empty());
+
+        let changed = properties
+            .apply(updates(vec![("a", update(None, None))]), false)
+            .unwrap();
+        assert!(changed.is_empty());
+    }
+
+    #[test]
+    fn host_only_keys_need_the_host() {
+        let mut properties = RoomProperties::default();
+        let host_only = |value| PropertyUpdate {
+            value: Some(value),
+            version: None,
+            host_only: Some(true),
+        };
+        assert!(properties
+            .apply(updates(vec![("a", host_only(json!(1)))]), false)
+            .is_none());
+        properties
+            .apply(updates(vec![("a", host_only(json!(1)))]), true)
+            .unwrap();
+
+        // The flag sticks until the host clears it
+        assert!(properties.apply(set("a", json!(2), None), false).is_none());
+        let changed = properties.apply(set("a", json!(2), None), true).unwrap();
+        assert!(changed["a"].as_ref().unwrap().host_only);
+    }
+}
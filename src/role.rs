@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::room;
+
+pub const HOST: &str = "host";
+pub const MODERATOR: &str = "moderator";
+pub const PLAYER: &str = "player";
+pub const SPECTATOR: &str = "spectator";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    CreateStream,
+    Publish,
+    Subscribe,
+    /// Broadcast on ws connections and data channels.
+    Send,
+    /// Send to a single user on ws connections and data channels.
+    Unicast,
+    SetProperties,
+    AssignRoles,
+}
+
+/// What the members holding a role may do. Fields left out of a custom role
+/// are not granted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct RolePermissions {
+    pub create_stream: bool,
+    pub publish: bool,
+    pub subscribe: bool,
+    pub send: bool,
+    pub unicast: bool,
+    pub set_properties: bool,
+    pub assign_roles: bool,
+}
+
+impl RolePermissions {
+    pub fn all() -> Self {
+        Self {
+            create_stream: true,
+            publish: true,
+            subscribe: true,
+            send: true,
+            unicast: true,
+            set_properties: true,
+            assign_roles: true,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::CreateStream => self.create_stream,
+            Permission::Publish => self.publish,
+            Permission::Subscribe => self.subscribe,
+            Permission::Send => self.send,
+            Permission::Unicast => self.unicast,
+            Permission::SetProperties => self.set_properties,
+            Permission::AssignRoles => self.assign_roles,
+        }
+    }
+}
+
+fn builtin_roles() -> HashMap<String, RolePermissions> {
+    let player = RolePermissions {
+        assign_roles: false,
+        ..RolePermissions::all()
+    };
+    let spectator = RolePermissions {
+        subscribe: true,
+        ..Default::default()
+    };
+    HashMap::from([
+        (MODERATOR.to_string(), RolePermissions::all()),
+        (PLAYER.to_string(), player),
+        (SPECTATOR.to_string(), spectator),
+    ])
+}
+
+/// Role definitions of a room and the role each member currently holds.
+/// The host always holds `HOST`, which grants everything; members without an
+/// assigned role hold the room's default role. Users who joined as spectators
+/// always hold `SPECTATOR` with the built-in, receive-only permissions.
+#[derive(Debug, Clone)]
+pub struct RoomRoles {
+    roles: HashMap<String, RolePermissions>,
+    custom: HashMap<String, RolePermissions>,
+    default_role: String,
+    members: HashMap<i32, String>,
+    host_id: Option<i32>,
+    spectator_events: bool,
+}
+
+impl Default for RoomRoles {
+    fn default() -> Self {
+        Self::new(HashMap::new(), PLAYER.to_string()).unwrap()
+    }
+}
+
+impl RoomRoles {
+    /// Custom roles may redefine the built-in ones except `HOST`. Returns
+    /// `None` if they try to, or if the default role is not defined.
+    pub fn new(custom: HashMap<String, RolePermissions>, default_role: String) -> Option<Self> {
+        if custom.contains_key(HOST) {
+            return None;
+        }
+        let mut roles = builtin_roles();
+        roles.extend(custom.clone());
+        if !roles.contains_key(&default_role) {
+            return None;
+        }
+        Some(Self {
+            roles,
+            custom,
+            default_role,
+            members: HashMap::new(),
+            host_id: None,
+            spectator_events: false,
+        })
+    }
+
+    /// Roles defined at room creation, as stored in the snapshot.
+    pub fn custom(&self) -> HashMap<String, RolePermissions> {
+        self.custom.clone()
+    }
+
+    pub fn default_role(&self) -> String {
+        self.default_role.clone()
+    }
+
+    pub fn role(&self, user_id: i32) -> String {
+        if self.host_id == Some(user_id) {
+            return HOST.to_string();
+        }
+        if room::is_spectator(user_id) {
+            return SPECTATOR.to_string();
+        }
+        match self.members.get(&user_id) {
+            Some(role) => role.clone(),
+            None => self.default_role.clone(),
+        }
+    }
+
+    pub fn permissions(&self, user_id: i32) -> RolePermissions {
+        if self.host_id == Some(user_id) {
+            return RolePermissions::all();
+        }
+        if room::is_spectator(user_id) {
+            return builtin_roles()[SPECTATOR];
+        }
+        let role = self.members.get(&user_id).unwrap_or(&self.default_role);
+        self.roles.get(role).copied().unwrap_or_default()
+    }
+
+    pub fn allows(&self, user_id: i32, permission: Permission) -> bool {
+        self.permissions(user_id).allows(permission)
+    }
+
+    /// The role must be defined and must not be `HOST`, which only follows
+    /// the host id, and spectators keep their role. Returns false otherwise.
+    pub fn assign(&mut self, user_id: i32, role: String) -> bool {
+        if role == HOST || !self.roles.contains_key(&role) || room::is_spectator(user_id) {
+            return false;
+        }
+        self.members.insert(user_id, role);
+        true
+    }
+
+    /// Forgets the role of a user who left, so the id starts over with the
+    /// default role.
+    pub fn remove(&mut self, user_id: i32) {
+        self.members.remove(&user_id);
+    }
+
+    pub fn set_host(&mut self, host_id: Option<i32>) {
+        self.host_id = host_id;
+    }
+
+    pub fn spectator_events(&self) -> bool {
+        self.spectator_events
+    }
+
+    pub fn set_spectator_events(&mut self, spectator_events: bool) {
+        self.spectator_events = spectator_events;
+    }
+
+    /// Whether OnOpen and OnClose are sent about the user. Spectators come
+    /// and go silently unless the room opted in.
+    pub fn notices_events(&self, user_id: i32) -> bool {
+        self.spectator_events || !room::is_spectator(user_id)
+    }
+}
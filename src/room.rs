@@ -1,35 +1,112 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use libws::GroupsManager;
+use chrono::Utc;
+use libws::{event_message, EventType, GroupsManager};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+use utoipa::ToSchema;
 
 use crate::config::Config;
-use crate::forward::rtc::client::Client;
+use crate::error::AppError;
+use crate::event::{RoomEvent, RoomEvents};
+use crate::forward::rtc::client::{Client, TokenDigest};
+use crate::key::RoomKey;
+use crate::property::{properties_message, Property, PropertyUpdate, RoomProperties};
 use crate::result::Result;
-use crate::route::room::RoomInfoJson;
+use crate::role::{self, Permission, RolePermissions, RoomRoles};
+use crate::route::room::{MemberInfoJson, RoomInfoJson};
 use crate::rtc::{Forwarder, ForwarderConfig};
+use crate::store::{RoomSnapshot, StoreWriter};
+use crate::{metrics, ROOMS};
 
 use tokio::sync::RwLock;
 
+/// Id sent in a HostChanged event when no client could be promoted.
+pub const NO_HOST: i32 = -1;
+
+pub const INVITE_CODE_LEN: usize = 6;
+
+/// Spectators get user ids from here on, so they never take a player slot.
+/// Player capacities must stay below it.
+pub const SPECTATOR_ID_BASE: i32 = 1_000_000;
+
+pub fn is_spectator(user_id: i32) -> bool {
+    user_id >= SPECTATOR_ID_BASE
+}
+
+/// How a `needs_host` room picks a new host once the current one leaves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HostMigrationPolicy {
+    /// Promote the client that joined the room first.
+    #[default]
+    Oldest,
+    /// Promote the client with the smallest user id.
+    LowestId,
+    /// Leave the room hostless until someone joins with the master key.
+    Disabled,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RosterAction {
+    Join,
+    Leave,
+    Update,
+}
+
+#[derive(Serialize)]
+struct RosterEventJson<'a> {
+    action: RosterAction,
+    member: &'a MemberInfoJson,
+}
+
+type WsDetached = HashMap<(u32, String), (TokenDigest, i64)>;
+
 #[derive(Clone)]
 pub struct Room {
     id: i32,
     name: String,
 
     needs_host: bool,
+    host_migration: HostMigrationPolicy,
+    host_id: Arc<RwLock<Option<i32>>>,
     is_public: bool,
     capacity: u32,
+    spectator_capacity: u32,
 
     client_map: Arc<RwLock<HashMap<i32, Client>>>,
+    empty_since: Arc<RwLock<i64>>,
+
+    banned_names: Arc<RwLock<HashSet<String>>>,
+    banned_ips: Arc<RwLock<HashSet<IpAddr>>>,
 
-    shared_key_hash: u32,
-    master_key_hash: u32,
+    shared_key_hash: RoomKey,
+    master_key_hash: RoomKey,
 
     description: String,
+    tags: Vec<String>,
+    create_time: i64,
+    invite_code: Arc<RwLock<Option<String>>>,
+
+    properties: Arc<RwLock<RoomProperties>>,
+    roles: Arc<RwLock<RoomRoles>>,
+
+    resume_grace: u64,
+    token_ttl: u64,
+    /// ws connections that dropped and may still be resumed, keyed by user
+    /// and group, with the digest of the token they connected with and the
+    /// time they dropped.
+    ws_detached: Arc<RwLock<WsDetached>>,
 
     forwarder: Arc<RwLock<Forwarder>>,
     group_manager: Arc<RwLock<GroupsManager>>,
+    events: Arc<RoomEvents>,
 }
 
 impl Room {
@@ -37,17 +114,28 @@ impl Room {
         id: i32,
         name: String,
         needs_host: bool,
+        host_migration: HostMigrationPolicy,
         is_public: bool,
         capacity: u32,
-        shared_key: String,
-        master_key: String,
+        spectator_capacity: u32,
+        shared_key: RoomKey,
+        master_key: RoomKey,
         description: String,
+        tags: Vec<String>,
+        properties: RoomProperties,
+        roles: RoomRoles,
         config: Config,
     ) -> Self {
         let client_map: Arc<RwLock<HashMap<i32, Client>>> = Default::default();
-        let forwarder = Arc::new(RwLock::new(Forwarder::new(ForwarderConfig::from_config(
-            config.clone(),
-        ))));
+        let properties = Arc::new(RwLock::new(properties));
+        let roles = Arc::new(RwLock::new(roles));
+        let events = Arc::new(RoomEvents::new(config.room.event_history.0));
+        let forwarder = Arc::new(RwLock::new(Forwarder::new(
+            ForwarderConfig::from_config(config.clone()),
+            properties.clone(),
+            roles.clone(),
+            events.clone(),
+        )));
         let group_manager = Arc::new(RwLock::new(GroupsManager::new()));
 
         let room: Room = Self {
@@ -55,25 +143,89 @@ impl Room {
             name: name,
 
             needs_host: needs_host,
+            host_migration: host_migration,
+            host_id: Default::default(),
             is_public: is_public,
 
             capacity: capacity,
+            spectator_capacity: spectator_capacity,
 
             client_map: client_map,
+            empty_since: Arc::new(RwLock::new(Utc::now().timestamp_millis())),
+
+            banned_names: Default::default(),
+            banned_ips: Default::default(),
 
-            shared_key_hash: utils::unique::hash_from_string(shared_key),
-            master_key_hash: utils::unique::hash_from_string(master_key),
+            shared_key_hash: shared_key,
+            master_key_hash: master_key,
 
             description: description,
+            tags: tags,
+            create_time: Utc::now().timestamp_millis(),
+            invite_code: Default::default(),
+
+            properties: properties,
+            roles: roles,
+
+            resume_grace: config.room.resume_grace,
+            token_ttl: config.room.token_ttl.0,
+            ws_detached: Default::default(),
 
             forwarder: forwarder,
             group_manager: group_manager,
+            events: events,
             //cfg: cfg,
         };
 
         room
     }
 
+    pub fn from_snapshot(snapshot: RoomSnapshot, config: Config) -> Self {
+        if snapshot.shared_key_hash.is_legacy() || snapshot.master_key_hash.is_legacy() {
+            warn!(
+                "room : {}, keys use the legacy unsalted hash, recreate the room to upgrade them",
+                snapshot.id
+            );
+        }
+        let roles = match snapshot.default_role {
+            Some(default_role) => RoomRoles::new(snapshot.roles, default_role),
+            None => RoomRoles::new(snapshot.roles, role::PLAYER.to_string()),
+        };
+        let roles = roles.unwrap_or_else(|| {
+            warn!(
+                "room : {}, invalid roles in snapshot, using the built-in ones",
+                snapshot.id
+            );
+            RoomRoles::default()
+        });
+        let mut roles = roles;
+        roles.set_spectator_events(snapshot.spectator_events);
+        let mut room = Self::new(
+            snapshot.id,
+            snapshot.name,
+            snapshot.needs_host,
+            snapshot.host_migration,
+            snapshot.is_public,
+            snapshot.capacity,
+            snapshot.spectator_capacity,
+            RoomKey::from_hash(snapshot.shared_key_hash),
+            RoomKey::from_hash(snapshot.master_key_hash),
+            snapshot.description,
+            snapshot.tags,
+            RoomProperties::new(snapshot.properties),
+            roles,
+            config,
+        );
+        if snapshot.create_time > 0 {
+            room.create_time = snapshot.create_time;
+        }
+        room.invite_code = Arc::new(RwLock::new(snapshot.invite_code));
+        // Not idle until the first client leaves, so a restart does not reap
+        // rooms before anyone had the chance to come back
+        room.empty_since = Arc::new(RwLock::new(0));
+        room
+    }
+
     pub fn id(&self) -> i32 {
         self.id
     }
@@ -82,6 +234,22 @@ impl Room {
         String::from_str(self.name.as_str()).unwrap()
     }
 
+    pub fn needs_host(&self) -> bool {
+        self.needs_host
+    }
+
+    pub fn host_migration(&self) -> HostMigrationPolicy {
+        self.host_migration
+    }
+
+    pub async fn host_id(&self) -> Option<i32> {
+        *self.host_id.read().await
+    }
+
+    pub async fn is_host(&self, user_id: i32) -> bool {
+        self.host_id().await == Some(user_id)
+    }
+
     pub fn is_public(&self) -> bool {
         self.is_public
     }
@@ -90,10 +258,40 @@ impl Room {
         self.capacity
     }
 
+    pub fn spectator_capacity(&self) -> u32 {
+        self.spectator_capacity
+    }
+
+    /// Whether spectators send OnOpen and OnClose like players do.
+    pub async fn spectator_events(&self) -> bool {
+        self.roles.read().await.spectator_events()
+    }
+
     pub fn client_map(&self) -> Arc<RwLock<HashMap<i32, Client>>> {
         self.client_map.clone()
     }
 
+    pub async fn client(&self, user_id: i32) -> Option<Client> {
+        self.client_map.read().await.get(&user_id).cloned()
+    }
+
+    /// Expiry of the user's current token, 0 if it never expires.
+    pub async fn token_expires_at(&self, user_id: i32) -> i64 {
+        self.client(user_id)
+            .await
+            .map(|client| client.token_expires_at())
+            .unwrap_or(0)
+    }
+
+    /// Milliseconds since the last client left, or 0 while the room is in use.
+    pub async fn idle_time(&self) -> i64 {
+        let empty_since = *self.empty_since.read().await;
+        if empty_since == 0 {
+            return 0;
+        }
+        Utc::now().timestamp_millis() - empty_since
+    }
+
     pub fn forwarder(&self) -> Arc<RwLock<Forwarder>> {
         self.forwarder.clone()
     }
@@ -102,31 +300,166 @@ impl Room {
         self.group_manager.clone()
     }
 
+    pub fn events(&self) -> Arc<RoomEvents> {
+        self.events.clone()
+    }
+
+    pub fn roles(&self) -> Arc<RwLock<RoomRoles>> {
+        self.roles.clone()
+    }
+
     pub fn description(&self) -> String {
         String::from_str(&self.description.as_str()).unwrap()
     }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
+
+    pub fn create_time(&self) -> i64 {
+        self.create_time
+    }
+
+    pub async fn invite_code(&self) -> Option<String> {
+        self.invite_code.read().await.clone()
+    }
+
+    /// Replaces the invite code. The code must come from `generate_invite_code`
+    /// with `ROOMS` locked, so that it is unique among live rooms.
+    pub async fn set_invite_code(&self, code: String) {
+        *self.invite_code.write().await = Some(code);
+    }
+
+    pub async fn properties(&self) -> HashMap<String, Property> {
+        self.properties.read().await.all()
+    }
+
+    /// Properties event carrying the whole set, for a connection that has
+    /// just been opened.
+    pub async fn properties_full_message(&self) -> Vec<u8> {
+        self.properties.read().await.full_message()
+    }
+
+    pub async fn role(&self, user_id: i32) -> String {
+        self.roles.read().await.role(user_id)
+    }
+
+    pub async fn permissions(&self, user_id: i32) -> RolePermissions {
+        self.roles.read().await.permissions(user_id)
+    }
+
+    pub async fn allows(&self, user_id: i32, permission: Permission) -> bool {
+        self.roles.read().await.allows(user_id, permission)
+    }
+
+    /// Number of players, spectators are not counted.
+    pub async fn occupancy(&self) -> u32 {
+        let clients = self.client_map.read().await;
+        clients.keys().filter(|id| !is_spectator(**id)).count() as u32
+    }
+
+    pub async fn spectators(&self) -> u32 {
+        let clients = self.client_map.read().await;
+        clients.keys().filter(|id| is_spectator(**id)).count() as u32
+    }
+
+    /// Number of slots a join without the master key can still take.
+    pub async fn free_slots(&self) -> u32 {
+        let clients = self.client_map.read().await;
+        let first = if self.needs_host { 1 } else { 0 };
+        (first..self.capacity as i32)
+            .filter(|i| !clients.contains_key(i))
+            .count() as u32
+    }
 }
 
 impl Room {
-    pub fn info(&self) -> RoomInfoJson {
+    pub async fn info(&self) -> RoomInfoJson {
         RoomInfoJson {
             id: self.id(),
             name: self.name(),
             capacity: self.capacity(),
             description: self.description(),
+            occupancy: self.occupancy().await,
+            spectator_capacity: self.spectator_capacity(),
+            spectators: self.spectators().await,
+            has_host: self.host_id().await.is_some(),
+            create_time: self.create_time(),
+            tags: self.tags(),
+            invite_code: self.invite_code().await,
+        }
+    }
+
+    pub async fn snapshot(&self) -> RoomSnapshot {
+        RoomSnapshot {
+            id: self.id(),
+            name: self.name(),
+            capacity: self.capacity(),
+            spectator_capacity: self.spectator_capacity(),
+            needs_host: self.needs_host(),
+            host_migration: self.host_migration(),
+            is_public: self.is_public(),
+            shared_key_hash: self.shared_key_hash.hash(),
+            master_key_hash: self.master_key_hash.hash(),
+            description: self.description(),
+            tags: self.tags(),
+            create_time: self.create_time(),
+            properties: self.properties().await,
+            invite_code: self.invite_code().await,
+            roles: self.roles.read().await.custom(),
+            default_role: Some(self.roles.read().await.default_role()),
+            spectator_events: self.spectator_events().await,
+        }
+    }
+
+    async fn member_info(&self, client: &Client) -> MemberInfoJson {
+        MemberInfoJson {
+            id: client.id(),
+            name: client.name(),
+            join_time: client.join_time(),
+            role: self.roles.read().await.role(client.id()),
+            spectator: is_spectator(client.id()),
+            streams: client.get_streams().await,
+            properties: client.properties().await,
         }
     }
 
-    pub fn auth_shared_key(&self, key: String) -> bool {
-        let hash = utils::unique::hash_from_string(key);
+    /// Players, or spectators if `spectators` is set, ordered by user id.
+    pub async fn roster(&self, spectators: bool) -> Vec<MemberInfoJson> {
+        let clients = self.client_map.read().await;
+        let mut clients: Vec<Client> = clients
+            .values()
+            .filter(|client| is_spectator(client.id()) == spectators)
+            .cloned()
+            .collect();
+        clients.sort_by_key(|client| client.id());
+
+        let mut roster = vec![];
+        for client in clients.iter() {
+            roster.push(self.member_info(client).await);
+        }
+        roster
+    }
 
-        self.shared_key_hash == hash
+    /// Runs argon2, so ROOMS must not be held while it is awaited.
+    pub async fn auth_shared_key(&self, key: String) -> bool {
+        self.shared_key_hash.verify_blocking(key).await
     }
 
-    pub fn auth_master_key(&self, key: String) -> bool {
-        let hash = utils::unique::hash_from_string(key);
+    /// Runs argon2, so ROOMS must not be held while it is awaited.
+    pub async fn auth_master_key(&self, key: String) -> bool {
+        self.master_key_hash.verify_blocking(key).await
+    }
+
+    /// Bans the name and the remote ip of the user for the lifetime of the room.
+    pub async fn ban(&self, user_name: String, remote_ip: IpAddr) {
+        self.banned_names.write().await.insert(user_name);
+        self.banned_ips.write().await.insert(remote_ip);
+    }
 
-        self.master_key_hash == hash
+    pub async fn is_banned(&self, user_name: &str, remote_ip: &IpAddr) -> bool {
+        self.banned_names.read().await.contains(user_name)
+            || self.banned_ips.read().await.contains(remote_ip)
     }
 
     pub async fn all_user_delete(&mut self) -> Result<bool> {
@@ -136,31 +469,67 @@ impl Room {
         drop(clients);
 
         for user_id in user_ids {
-            self.user_delete(user_id, 0, false).await?;
+            self.user_delete(user_id, "", false).await?;
         }
 
         return Ok(true);
     }
 
+    /// Removes every client and releases the room's forwarder and ws groups.
+    pub async fn close(&mut self) -> Result<()> {
+        self.all_user_delete().await?;
+
+        let forwarder = self.forwarder.read().await;
+        forwarder.close().await;
+        drop(forwarder);
+
+        let group_manager = self.group_manager.read().await;
+        group_manager.clear().await;
+        drop(group_manager);
+
+        self.events.send(RoomEvent::RoomDelete);
+
+        Ok(())
+    }
+
     pub async fn user_delete(
         &mut self,
         user_id: i32,
-        token: u32,
+        token: &str,
         check_token: bool,
     ) -> Result<bool> {
         let clients = self.client_map.read().await;
         let client = clients.get(&user_id).cloned();
         drop(clients);
 
+        // Nothing of the user is touched before the token matches
+        let is_ok = client
+            .as_ref()
+            .is_some_and(|client| client.check_token(token));
+        if check_token && !is_ok {
+            return Ok(false);
+        }
+
         let group_manager = self.group_manager();
         let group_manager = group_manager.write().await;
         group_manager.end_user(user_id as u32).await;
         drop(group_manager);
+        // Nothing of the user may be resumed by whoever gets the slot next
+        self.ws_detached
+            .write()
+            .await
+            .retain(|(detached_user, _), _| *detached_user != user_id as u32);
 
         if let Some(mut client) = client {
-            if check_token && !client.check_token(token.clone()) {
-                return Ok(false);
-            }
+            // Requests holding a copy of the client must not pass auth anymore
+            client.revoke_token();
+
+            let member = self.member_info(&client).await;
+
+            let forwarder = self.forwarder.read().await;
+            forwarder.close_user_subscribes(user_id as u32).await;
+            forwarder.end_user_resume(user_id as u32).await;
+            drop(forwarder);
 
             for stream in client.get_streams().await {
                 let forwarder = self.forwarder.write().await;
@@ -169,40 +538,284 @@ impl Room {
                 client.remove_stream(stream.clone()).await?;
             }
 
+            self.roles.write().await.remove(user_id);
+
             let mut clients = self.client_map.write().await;
             clients.remove(&user_id);
+            if clients.is_empty() {
+                *self.empty_since.write().await = Utc::now().timestamp_millis();
+            }
             drop(clients);
 
+            self.notice_roster(RosterAction::Leave, &member).await;
+            self.events.send(RoomEvent::UserLeave { user_id });
+
+            if self.is_host(user_id).await {
+                self.migrate_host().await;
+            }
+
             return Ok(true);
         }
 
         Ok(false)
     }
 
-    async fn do_join(&self, user_id: i32, _token: u32) -> Result<()> {
+    /// Hands the host role over to another client according to the room's
+    /// `HostMigrationPolicy` and notifies every member. The current host is
+    /// never picked again, so this also works when the host has only dropped
+    /// its connections but is still in `client_map`.
+    pub async fn migrate_host(&self) {
+        let prev_host = match self.host_id().await {
+            Some(prev_host) => prev_host,
+            None => return,
+        };
+
+        let clients = self.client_map.read().await;
+        let candidates = clients
+            .values()
+            .filter(|client| client.id() != prev_host && !is_spectator(client.id()));
+        let next_host = match self.host_migration {
+            HostMigrationPolicy::Oldest => candidates
+                .min_by_key(|client| (client.join_time(), client.id()))
+                .map(|client| client.id()),
+            HostMigrationPolicy::LowestId => candidates.map(|client| client.id()).min(),
+            HostMigrationPolicy::Disabled => None,
+        };
+        drop(clients);
+
+        self.set_host(next_host).await;
+    }
+
+    async fn set_host(&self, host: Option<i32>) {
+        let mut host_id = self.host_id.write().await;
+        let prev_host = *host_id;
+        if prev_host == host {
+            return;
+        }
+        *host_id = host;
+        self.roles.write().await.set_host(host);
+        drop(host_id);
+
+        let host = host.unwrap_or(NO_HOST);
+        let prev_host = prev_host.unwrap_or(NO_HOST);
+        info!(
+            "room : {}, host changed from {} to {}",
+            self.id, prev_host, host
+        );
+
+        let message = event_message(EventType::HostChanged, host as u32, prev_host as u32);
+        self.send_message_to_all(message).await;
+    }
+
+    /// Writes the message to every ws connection and data channel of the
+    /// room, including the ones of the user the event is about.
+    async fn send_message_to_all(&self, message: Vec<u8>) {
+        let group_manager = self.group_manager.read().await;
+        group_manager.send_message_to_all(message.clone()).await;
+        drop(group_manager);
+
+        let forwarder = self.forwarder.read().await;
+        forwarder.send_message_to_all(message).await;
+    }
+
+    async fn notice_roster(&self, action: RosterAction, member: &MemberInfoJson) {
+        let payload = serde_json::to_vec(&RosterEventJson { action, member }).unwrap();
+        let id = member.id as u32;
+        let message = [event_message(EventType::Roster, id, id), payload].concat();
+        self.send_message_to_all(message).await;
+    }
+
+    /// Merges the given keys into the user's custom properties and pushes the
+    /// updated roster entry. Returns false if the user is not in the room.
+    pub async fn set_user_properties(
+        &self,
+        user_id: i32,
+        updates: HashMap<String, Option<Value>>,
+    ) -> bool {
+        let clients = self.client_map.read().await;
+        let client = clients.get(&user_id).cloned();
+        drop(clients);
+
+        let client = match client {
+            Some(client) => client,
+            None => return false,
+        };
+        client.set_properties(updates).await;
+
+        let member = self.member_info(&client).await;
+        self.notice_roster(RosterAction::Update, &member).await;
+
+        true
+    }
+
+    /// Gives the user one of the room's roles and pushes the updated roster
+    /// entry. Returns false if the user is not in the room or the role can
+    /// not be assigned.
+    pub async fn set_role(&self, user_id: i32, role: String) -> bool {
+        let client = match self.client(user_id).await {
+            Some(client) => client,
+            None => return false,
+        };
+        if !self.roles.write().await.assign(user_id, role.clone()) {
+            return false;
+        }
+        info!("room : {}, user : {} is now {}", self.id, user_id, role);
+
+        let member = self.member_info(&client).await;
+        self.notice_roster(RosterAction::Update, &member).await;
+
+        true
+    }
+
+    /// Applies the updates atomically and pushes the changed keys to every
+    /// member. `is_host` allows host-only keys to be written. Returns false
+    /// when a version check or a host-only key rejected the updates.
+    pub async fn set_properties(
+        &self,
+        user_id: i32,
+        updates: HashMap<String, PropertyUpdate>,
+        is_host: bool,
+    ) -> bool {
+        let mut properties = self.properties.write().await;
+        let changed = match properties.apply(updates, is_host) {
+            Some(changed) => changed,
+            None => return false,
+        };
+        if changed.is_empty() {
+            return true;
+        }
+        // The message is built while still holding the lock, so events reach
+        // clients in the same order as the versions were assigned.
+        let message = properties_message(user_id as u32, false, &changed);
+        self.send_message_to_all(message).await;
+        drop(properties);
+
+        info!(
+            "room : {}, properties changed by {} : {:?}",
+            self.id,
+            user_id,
+            changed.keys().collect::<Vec<_>>()
+        );
+
+        true
+    }
+
+    /// Returns true if the user reattaches with the same token to a ws group
+    /// it dropped less than `resume_grace` ago, in which case no OnOpen is
+    /// sent. A connection with another token first ends the dropped one.
+    pub async fn ws_attach(&self, stream: &str, user_id: u32, token: &str) -> bool {
+        let mut ws_detached = self.ws_detached.write().await;
+        let detached = ws_detached.remove(&(user_id, stream.to_string()));
+        drop(ws_detached);
+        match detached {
+            Some((detached_token, _)) if detached_token.matches(&TokenDigest::new(token)) => true,
+            Some(_) => {
+                self.ws_leave(stream.to_string(), user_id).await;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Called once a ws connection of the user has gone. The user leaves the
+    /// group, which sends OnClose, after `resume_grace` unless it reattached.
+    pub async fn ws_detach(&self, stream: String, user_id: u32, token: String) {
+        if self.resume_grace == 0 {
+            self.ws_leave(stream, user_id).await;
+            return;
+        }
+
+        let key = (user_id, stream);
+        let detached_at = Utc::now().timestamp_millis();
+        self.ws_detached
+            .write()
+            .await
+            .insert(key.clone(), (TokenDigest::new(&token), detached_at));
+
+        let room = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(room.resume_grace)).await;
+            let mut ws_detached = room.ws_detached.write().await;
+            if ws_detached.get(&key).map(|(_, at)| *at) != Some(detached_at) {
+                return; // Resumed, or dropped again and handled by a later timer
+            }
+            ws_detached.remove(&key);
+            drop(ws_detached);
+            info!(
+                "room : {}, user : {} did not resume {} in time",
+                room.id, user_id, key.1
+            );
+            room.ws_leave(key.1, user_id).await;
+        });
+    }
+
+    async fn ws_leave(&self, stream: String, user_id: u32) {
+        let group_manager = self.group_manager.write().await;
+        let _ = group_manager.leave_group(stream, user_id).await;
+        let is_dropped = group_manager.user_groups(user_id).await.is_empty();
+        drop(group_manager);
+
+        // The host lost its last relay connection, so nobody can reach it anymore.
+        if is_dropped && self.is_host(user_id as i32).await {
+            self.migrate_host().await;
+        }
+    }
+
+    /// Expiry of a token issued now, 0 if tokens never expire.
+    fn new_token_expires_at(&self) -> i64 {
+        if self.token_ttl == 0 {
+            return 0;
+        }
+        Utc::now().timestamp_millis() + self.token_ttl as i64
+    }
+
+    /// Issues a new token for the user and invalidates the current one.
+    /// Returns the token and its expiry, or `None` if the user is not in
+    /// the room.
+    pub async fn refresh_token(&self, user_id: i32) -> Option<(String, i64)> {
+        let clients = self.client_map.read().await;
+        let client = clients.get(&user_id)?;
+        let token = utils::unique::generate_token();
+        let expires_at = self.new_token_expires_at();
+        client.set_token(&token, expires_at);
+        Some((token, expires_at))
+    }
+
+    async fn do_join(&self, user_id: i32) -> Result<()> {
+        let notices_events = self.roles.read().await.notices_events(user_id);
         let group_manager = self.group_manager();
         let group_manager = group_manager.write().await;
         group_manager.init_user(user_id as u32).await;
+        if !notices_events {
+            group_manager.set_silent(user_id as u32).await;
+        }
         drop(group_manager);
         Ok(())
     }
 
+    /// With `as_host` the host slot is taken, the caller has checked the
+    /// master key.
     pub async fn join(
         &mut self,
         user_name: String,
-        master_key: String,
+        as_host: bool,
+        remote_ip: IpAddr,
         user_id: &mut i32,
-        token: &mut u32,
-    ) -> Result<bool> {
-        let mut clients = self.client_map.write().await;
+        token: &mut String,
+    ) -> Result<()> {
+        if self.is_banned(&user_name, &remote_ip).await {
+            return Err(AppError::Banned);
+        }
+
+        let clients = self.client_map.read().await;
 
         if self.needs_host {
-            if master_key != "" {
-                if !self.auth_master_key(master_key) || clients.contains_key(&0) {
-                    return Ok(false);
+            if as_host {
+                if clients.contains_key(&0) {
+                    return Err(AppError::conflict("host slot is taken"));
                 }
                 *user_id = 0;
-                *token = utils::unique::generate_unique_u32();
+                *token = utils::unique::generate_token();
             } else {
                 let mut is_ok = false;
                 for i in 1..self.capacity.try_into().unwrap() {
@@ -211,11 +824,11 @@ impl Room {
                     }
                     is_ok = true;
                     *user_id = i.try_into().unwrap();
-                    *token = utils::unique::generate_unique_u32();
+                    *token = utils::unique::generate_token();
                     break;
                 }
                 if !is_ok {
-                    return Ok(false);
+                    return Err(AppError::RoomFull);
                 }
             }
         } else {
@@ -226,20 +839,151 @@ impl Room {
                 }
                 is_ok = true;
                 *user_id = i.try_into().unwrap();
-                *token = utils::unique::generate_unique_u32();
+                *token = utils::unique::generate_token();
                 break;
             }
             if !is_ok {
-                return Ok(false);
+                return Err(AppError::RoomFull);
             }
         }
+        drop(clients);
 
-        clients.insert(
-            *user_id,
-            Client::new(user_id.clone(), token.clone(), user_name.clone()).await?,
-        );
-        self.do_join(user_id.clone(), token.clone()).await?;
+        self.add_client(user_name, remote_ip, *user_id, token)
+            .await?;
+
+        if self.needs_host && *user_id == 0 {
+            self.set_host(Some(0)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Joins with an id from `SPECTATOR_ID_BASE` on, limited by the room's
+    /// spectator capacity instead of its player capacity.
+    pub async fn join_spectator(
+        &mut self,
+        user_name: String,
+        remote_ip: IpAddr,
+        user_id: &mut i32,
+        token: &mut String,
+    ) -> Result<()> {
+        if self.is_banned(&user_name, &remote_ip).await {
+            return Err(AppError::Banned);
+        }
+
+        let clients = self.client_map.read().await;
+        let last = SPECTATOR_ID_BASE + self.spectator_capacity as i32;
+        let free_id = (SPECTATOR_ID_BASE..last).find(|i| !clients.contains_key(i));
+        drop(clients);
+        match free_id {
+            Some(free_id) => *user_id = free_id,
+            None => return Err(AppError::RoomFull),
+        }
+        *token = utils::unique::generate_token();
+
+        self.add_client(user_name, remote_ip, *user_id, token)
+            .await?;
+
+        Ok(())
+    }
 
-        Ok(true)
+    async fn add_client(
+        &self,
+        user_name: String,
+        remote_ip: IpAddr,
+        user_id: i32,
+        token: &str,
+    ) -> Result<()> {
+        let client = Client::new(
+            user_id,
+            token,
+            self.new_token_expires_at(),
+            user_name.clone(),
+            remote_ip,
+        )
+        .await?;
+        self.client_map
+            .write()
+            .await
+            .insert(user_id, client.clone());
+        *self.empty_since.write().await = 0;
+        self.do_join(user_id).await?;
+
+        let member = self.member_info(&client).await;
+        self.notice_roster(RosterAction::Join, &member).await;
+        self.events.send(RoomEvent::UserJoin {
+            user_id,
+            name: user_name,
+        });
+
+        Ok(())
+    }
+}
+
+/// Picks a room id that is not used by any entry of `rooms`.
+pub fn generate_room_id(rooms: &HashMap<i32, Room>) -> i32 {
+    loop {
+        let room_id = utils::unique::generate_unique_i32();
+        if !rooms.contains_key(&room_id) {
+            return room_id;
+        }
+    }
+}
+
+/// Picks an invite code that no room of `rooms` currently uses.
+pub async fn generate_invite_code(rooms: &HashMap<i32, Room>) -> String {
+    let mut codes = HashSet::new();
+    for room in rooms.values() {
+        if let Some(code) = room.invite_code().await {
+            codes.insert(code);
+        }
+    }
+    loop {
+        let code = utils::unique::generate_code(INVITE_CODE_LEN);
+        if !codes.contains(&code) {
+            return code;
+        }
+    }
+}
+
+/// Finds the live room using the invite code. Codes are case-insensitive.
+pub async fn find_by_invite_code(rooms: &HashMap<i32, Room>, code: &str) -> Option<i32> {
+    let code = code.trim().to_uppercase();
+    for room in rooms.values() {
+        if room.invite_code().await.as_deref() == Some(code.as_str()) {
+            return Some(room.id());
+        }
+    }
+    None
+}
+
+pub async fn idle_check_tick(store: StoreWriter, idle_timeout: u64) {
+    if idle_timeout == 0 {
+        return;
+    }
+    let idle_timeout_i64: i64 = idle_timeout.try_into().unwrap();
+    loop {
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let mut rooms = ROOMS.lock().await;
+        let mut remove_rooms = vec![];
+        for (room_id, room) in rooms.iter() {
+            if room.idle_time().await > idle_timeout_i64 {
+                remove_rooms.push(*room_id);
+            }
+        }
+        for room_id in remove_rooms.iter() {
+            if let Some(mut room) = rooms.remove(room_id) {
+                if let Err(err) = room.close().await {
+                    warn!("room : {}, close err: {:?}", room_id, err);
+                }
+                store.remove(*room_id);
+                metrics::EXPIRED_ROOMS.inc();
+                info!(
+                    "room : {}, idle timeout, expired rooms : {}",
+                    room_id,
+                    metrics::EXPIRED_ROOMS.get()
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,282 @@
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::routing::{delete, get};
+use axum::Router;
+use http::response::StreamInfo;
+use http::StatusCode;
+use serde::Serialize;
+use tracing::{debug, info};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::AppError;
+use crate::http;
+use crate::result::Result;
+use crate::room::Room;
+use crate::route::room::{MemberInfoJson, RoomInfoJson};
+use crate::route::*;
+use crate::ROOMS;
+
+/// Operator routes, reaching every room without its keys. They need
+/// `server_admin` unless `[auth.routes]` says otherwise.
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/admin/rooms", get(admin_rooms))
+        .route(
+            "/admin/rooms/:room_id",
+            get(admin_room).delete(admin_delete_room),
+        )
+        .route(
+            "/admin/rooms/:room_id/users/:user_id",
+            delete(admin_delete_user),
+        )
+        .route(
+            "/admin/rooms/:room_id/streams/:stream",
+            delete(admin_delete_stream),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        admin_rooms,
+        admin_room,
+        admin_delete_room,
+        admin_delete_user,
+        admin_delete_stream
+    ),
+    components(schemas(AdminRoomJson, ClientJson, GroupJson))
+)]
+pub struct Api;
+
+#[derive(Serialize, ToSchema)]
+struct AdminRoomJson {
+    #[serde(flatten)]
+    info: RoomInfoJson,
+    is_public: bool,
+    host_id: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ClientJson {
+    #[serde(flatten)]
+    member: MemberInfoJson,
+    remote_ip: String,
+    token_expires_at: i64,
+    /// ws groups the user is in.
+    groups: Vec<String>,
+}
+
+/// A ws group, one per stream the users talk over.
+#[derive(Serialize, ToSchema)]
+struct GroupJson {
+    name: String,
+    users: Vec<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RoomDetailJson {
+    #[serde(flatten)]
+    room: AdminRoomJson,
+    /// Players, then spectators.
+    clients: Vec<ClientJson>,
+    groups: Vec<GroupJson>,
+    streams: Vec<StreamInfo>,
+}
+
+async fn room_json(room: &Room) -> AdminRoomJson {
+    AdminRoomJson {
+        info: room.info().await,
+        is_public: room.is_public(),
+        host_id: room.host_id().await,
+    }
+}
+
+/// Every room, private ones included, ordered by id.
+#[utoipa::path(
+    get,
+    path = "/admin/rooms",
+    tag = "admin",
+    responses((status = 200, body = Vec<AdminRoomJson>))
+)]
+async fn admin_rooms() -> Result<Response> {
+    debug!("HTTP GET /admin/rooms");
+
+    let mut rooms: Vec<Room> = ROOMS.lock().await.values().cloned().collect();
+    rooms.sort_by_key(|room| room.id());
+
+    let mut infos = vec![];
+    for room in rooms.iter() {
+        infos.push(room_json(room).await);
+    }
+
+    Ok(http::create_response(
+        Body::from(serde_json::to_string(&infos).unwrap()),
+        StatusCode::OK,
+    ))
+}
+
+/// The room with its clients, ws groups, and streams with the state of each
+/// peer session.
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}",
+    tag = "admin",
+    params(("room_id" = i32, Path, description = "Room to inspect")),
+    responses((status = 200, body = inline(RoomDetailJson)))
+)]
+async fn admin_room(Path(room_id): Path<i32>) -> Result<Response> {
+    debug!("HTTP GET /admin/rooms/room_id");
+
+    let room = match ROOMS.lock().await.get(&room_id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    let group_manager = room.group_manager();
+    let group_manager = group_manager.read().await;
+    let mut clients = vec![];
+    let mut members = room.roster(false).await;
+    members.extend(room.roster(true).await);
+    for member in members {
+        // Left since the roster was taken
+        let client = match room.client(member.id).await {
+            Some(client) => client,
+            None => continue,
+        };
+        clients.push(ClientJson {
+            remote_ip: client.remote_ip().to_string(),
+            token_expires_at: room.token_expires_at(member.id).await,
+            groups: group_manager.user_groups(member.id as u32).await,
+            member,
+        });
+    }
+    let groups = group_manager
+        .groups()
+        .await
+        .into_iter()
+        .map(|(name, users)| GroupJson { name, users })
+        .collect();
+    drop(group_manager);
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    let streams: Vec<StreamInfo> = forwarder
+        .forward_infos(vec![])
+        .await
+        .into_iter()
+        .map(|forward_info| forward_info.into())
+        .collect();
+    drop(forwarder);
+
+    let response = RoomDetailJson {
+        room: room_json(&room).await,
+        clients,
+        groups,
+        streams,
+    };
+
+    Ok(http::create_response(
+        Body::from(serde_json::to_string(&response).unwrap()),
+        StatusCode::OK,
+    ))
+}
+
+/// Deletes the room as `/v2/room/delete` does, without the master key.
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}",
+    tag = "admin",
+    params(("room_id" = i32, Path, description = "Room to delete")),
+    responses((status = 204, description = "Deleted"))
+)]
+async fn admin_delete_room(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+) -> Result<Response> {
+    debug!("HTTP DELETE /admin/rooms/room_id");
+
+    let mut rooms = ROOMS.lock().await;
+
+    let room: &mut Room = match rooms.get_mut(&room_id) {
+        Some(room) => room,
+        None => return Err(AppError::RoomNotFound),
+    };
+    room.close().await?;
+    rooms.remove(&room_id);
+    state.store.remove(room_id);
+
+    info!("room : {}, deleted by admin", room_id);
+
+    Ok(http::create_response(Body::empty(), StatusCode::NO_CONTENT))
+}
+
+/// Disconnects the user as `/v2/room/kick` does, without the master key.
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/users/{user_id}",
+    tag = "admin",
+    params(
+        ("room_id" = i32, Path, description = "Room of the user"),
+        ("user_id" = i32, Path, description = "User to disconnect")
+    ),
+    responses((status = 204, description = "Disconnected"))
+)]
+async fn admin_delete_user(Path((room_id, user_id)): Path<(i32, i32)>) -> Result<Response> {
+    debug!("HTTP DELETE /admin/rooms/room_id/users/user_id");
+
+    let mut rooms = ROOMS.lock().await;
+
+    let room: &mut Room = match rooms.get_mut(&room_id) {
+        Some(room) => room,
+        None => return Err(AppError::RoomNotFound),
+    };
+    if !room.user_delete(user_id, "", false).await? {
+        return Err(AppError::UserNotFound(user_id));
+    }
+
+    info!(
+        "room : {}, user : {} disconnected by admin",
+        room_id, user_id
+    );
+
+    Ok(http::create_response(Body::empty(), StatusCode::NO_CONTENT))
+}
+
+/// Closes the stream with all of its sessions, as `/v2/stream/destroy` does
+/// for its publisher.
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/streams/{stream}",
+    tag = "admin",
+    params(
+        ("room_id" = i32, Path, description = "Room of the stream"),
+        ("stream" = String, Path, description = "Stream to close")
+    ),
+    responses((status = 204, description = "Closed"))
+)]
+async fn admin_delete_stream(Path((room_id, stream)): Path<(i32, String)>) -> Result<Response> {
+    debug!("HTTP DELETE /admin/rooms/room_id/streams/stream");
+
+    let room = match ROOMS.lock().await.get(&room_id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    forwarder.stream_delete(stream.clone()).await?;
+    drop(forwarder);
+
+    let client_map = room.client_map();
+    let clients: Vec<_> = client_map.read().await.values().cloned().collect();
+    for mut client in clients {
+        if client.get_streams().await.contains(&stream) {
+            client.remove_stream(stream.clone()).await?;
+        }
+    }
+
+    info!("room : {}, stream : {} closed by admin", room_id, stream);
+
+    Ok(http::create_response(Body::empty(), StatusCode::NO_CONTENT))
+}
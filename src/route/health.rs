@@ -0,0 +1,30 @@
+use axum::body::Body;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use http::StatusCode;
+use utoipa::OpenApi;
+
+use crate::http;
+use crate::result::Result;
+use crate::route::*;
+
+/// Liveness probe, served without credentials.
+pub fn route() -> Router<AppState> {
+    Router::new().route("/health", get(health))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(health))]
+pub struct Api;
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "server",
+    security(()),
+    responses((status = 200, content_type = "text/plain", body = String))
+)]
+async fn health() -> Result<Response> {
+    Ok(http::create_response(Body::from("OK"), StatusCode::OK))
+}
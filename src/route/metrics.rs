@@ -0,0 +1,39 @@
+use axum::body::Body;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use http::StatusCode;
+use tracing::debug;
+use utoipa::OpenApi;
+
+use crate::http;
+use crate::metrics::{ENCODER, REGISTRY};
+use crate::result::Result;
+use crate::route::*;
+
+pub fn route() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(metrics))]
+pub struct Api;
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "server",
+    responses((
+        status = 200,
+        description = "Prometheus text format",
+        content_type = "text/plain",
+        body = String
+    ))
+)]
+async fn metrics() -> Result<Response> {
+    debug!("HTTP GET /metrics");
+
+    let body = ENCODER.encode_to_string(&REGISTRY.gather())?;
+
+    Ok(http::create_response(Body::from(body), StatusCode::OK))
+}
@@ -1,19 +1,34 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
 
-use axum::body::Body;
-use axum::response::Response;
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Path, Query, Request};
+use axum::http::request::Parts;
+use axum::http::{Method, Uri};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
-use http::StatusCode;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+use utoipa::IntoParams;
 
 use crate::config::Config;
+use crate::error::AppError;
 use crate::forward::rtc::client::Client;
-use crate::http::BodyUtil;
+use crate::lockout;
+use crate::role::Permission;
 use crate::room::Room;
-use crate::{http, ROOMS};
+use crate::store::StoreWriter;
+use crate::webhook::{AuthWebhook, WebhookDecision, WebhookRequest};
+use crate::ROOMS;
 
+pub mod admin;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
 pub mod room;
 pub mod rtc;
 pub mod r#static;
@@ -22,59 +37,262 @@ pub mod ws;
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
+    pub store: StoreWriter,
+    pub webhook: Option<Arc<AuthWebhook>>,
 }
 
-pub fn parse_base64_into_json<T>(params: &HashMap<String, String>) -> Result<T, Response>
+/// Asks the auth webhook, if one is configured, whether the request may go
+/// ahead.
+pub async fn authorize(
+    state: &AppState,
+    request: WebhookRequest,
+) -> Result<Option<WebhookDecision>, AppError> {
+    let webhook = match &state.webhook {
+        Some(webhook) => webhook,
+        None => return Ok(None),
+    };
+    let decision = webhook.authorize(&request).await?;
+    if !decision.allow {
+        return Err(AppError::WebhookDenied(decision.reason));
+    }
+    Ok(Some(decision))
+}
+
+/// Gives a user who has just joined the role and metadata the auth webhook
+/// answered with.
+pub async fn apply_decision(room: &Room, user_id: i32, decision: Option<WebhookDecision>) {
+    let decision = match decision {
+        Some(decision) => decision,
+        None => return,
+    };
+    if !decision.metadata.is_empty() {
+        room.set_user_properties(user_id, decision.metadata).await;
+    }
+    if let Some(role) = decision.role {
+        if !room.set_role(user_id, role.clone()).await {
+            warn!(
+                "room : {}, auth webhook role {} can not be assigned",
+                room.id(),
+                role
+            );
+        }
+    }
+}
+
+/// Headers carrying the room credentials of `/v2` requests, so that they
+/// stay out of urls and access logs, with the field each one fills in.
+pub const CREDENTIAL_HEADERS: [(&str, &str); 3] = [
+    ("x-room-shared-key", "shared_key"),
+    ("x-room-master-key", "master_key"),
+    ("x-room-token", "token"),
+];
+
+/// `CREDENTIAL_HEADERS` as described in the OpenAPI document. Each header
+/// only applies to routes whose arguments have the field.
+#[allow(dead_code)]
+#[derive(IntoParams)]
+#[into_params(parameter_in = Header)]
+pub struct CredentialHeaders {
+    /// Fills in `shared_key`.
+    #[param(rename = "x-room-shared-key")]
+    pub shared_key: Option<String>,
+    /// Fills in `master_key`.
+    #[param(rename = "x-room-master-key")]
+    pub master_key: Option<String>,
+    /// Fills in `token`.
+    #[param(rename = "x-room-token")]
+    pub token: Option<String>,
+}
+
+/// Arguments of a request. The original routes take them as base64 JSON in
+/// the `:base64` path segment. `/v2` routes take a JSON body, or the query
+/// for GET, e.g. WebSocket upgrades; credential headers override the fields.
+pub struct RequestArgs<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for RequestArgs<T>
+where
+    T: DeserializeOwned + Serialize,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = request.into_parts();
+        let path = Path::<HashMap<String, String>>::from_request_parts(&mut parts, state).await;
+        if let Ok(Path(params)) = path {
+            if params.contains_key("base64") {
+                return parse_base64_into_json(&params).map(RequestArgs);
+            }
+        }
+
+        let credentials = credentials(&parts);
+
+        if parts.method == Method::GET {
+            return query_args(&parts, credentials).map(RequestArgs);
+        }
+
+        let bytes = match Bytes::from_request(Request::from_parts(parts, body), state).await {
+            Ok(bytes) => bytes,
+            Err(err) => return Err(AppError::invalid_request(err.body_text())),
+        };
+        let mut json = match bytes.is_empty() {
+            true => Value::Object(Default::default()),
+            false => match serde_json::from_slice::<Value>(&bytes) {
+                Ok(json) => json,
+                Err(err) => return Err(args_error(err)),
+            },
+        };
+        if let Value::Object(object) = &mut json {
+            for (field, value) in credentials {
+                let entry = object.entry(field).or_insert(Value::String(String::new()));
+                if let Some(value) = value {
+                    *entry = Value::String(value);
+                }
+            }
+        }
+        match serde_json::from_value(json) {
+            Ok(args) => Ok(RequestArgs(args)),
+            Err(err) => Err(args_error(err)),
+        }
+    }
+}
+
+/// Arguments of a request whose body is something else, e.g. the SDP offer
+/// of a WHIP or WHEP session: the query, with credential headers overriding
+/// the fields.
+pub struct QueryArgs<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for QueryArgs<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        query_args(parts, credentials(parts)).map(QueryArgs)
+    }
+}
+
+/// The value of each credential header, by the field it fills in.
+fn credentials(parts: &Parts) -> Vec<(&'static str, Option<String>)> {
+    CREDENTIAL_HEADERS
+        .iter()
+        .map(|(name, field)| {
+            let value = parts
+                .headers
+                .get(*name)
+                .and_then(|value| value.to_str().ok());
+            (*field, value.map(|value| value.to_string()))
+        })
+        .collect()
+}
+
+fn query_args<T>(parts: &Parts, credentials: Vec<(&str, Option<String>)>) -> Result<T, AppError>
+where
+    T: DeserializeOwned,
+{
+    // A credential left out counts as empty, like an unset master key
+    let query = parts.uri.query().unwrap_or("");
+    let mut pairs: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    for (field, value) in credentials {
+        let entry = pairs.entry(field.to_string()).or_default();
+        if let Some(value) = value {
+            *entry = value;
+        }
+    }
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish();
+    let uri: Uri = match format!("/?{}", query).parse() {
+        Ok(uri) => uri,
+        Err(err) => return Err(args_error(err)),
+    };
+    match Query::<T>::try_from_uri(&uri) {
+        Ok(Query(args)) => Ok(args),
+        Err(err) => Err(args_error(err.body_text())),
+    }
+}
+
+fn args_error(err: impl ToString) -> AppError {
+    AppError::invalid_request(err)
+}
+
+pub fn parse_base64_into_json<T>(params: &HashMap<String, String>) -> Result<T, AppError>
 where
     T: DeserializeOwned + Serialize,
 {
     if !params.contains_key("base64") {
-        return Err(http::create_response(
-            Body::from("Missing Params".to_string()),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::invalid_request("Missing Params"));
     }
     let json_obj = match BASE64_STANDARD.decode(params.get("base64").unwrap()) {
         Ok(json_obj) => json_obj,
-        Err(err) => {
-            return Err(http::create_response(
-                Body::from(err.to_string()),
-                StatusCode::NOT_ACCEPTABLE,
-            ))
-        }
+        Err(err) => return Err(AppError::invalid_request(err)),
     };
     let json: T = match serde_json::from_slice(&json_obj) {
         Ok(json) => json,
-        Err(err) => {
-            return Err(http::create_response(
-                Body::from(err.to_string()),
-                StatusCode::NOT_ACCEPTABLE,
-            ))
-        }
+        Err(err) => return Err(AppError::invalid_request(err)),
     };
     Ok(json)
 }
 
+/// Fails with 429 and Retry-After while the remote ip or the room is locked
+/// out after failed credential checks.
+pub fn check_lockout(remote_ip: IpAddr, room_id: i32) -> Result<(), AppError> {
+    match lockout::retry_after(remote_ip, room_id) {
+        Some(retry_after) => Err(AppError::TooManyAttempts(retry_after)),
+        None => Ok(()),
+    }
+}
+
+/// `check_lockout` for the credentials of a user already in the room. Only
+/// the remote ip is checked up front, the room's lockout applies once the
+/// check fails, so that the failures of others can not lock members out.
+pub fn check_user_lockout(remote_ip: IpAddr) -> Result<(), AppError> {
+    match lockout::ip_retry_after(remote_ip) {
+        Some(retry_after) => Err(AppError::TooManyAttempts(retry_after)),
+        None => Ok(()),
+    }
+}
+
+/// Records a failed check of a user's credentials, which fails with `err`,
+/// or with 429 while the room is locked out.
+pub fn user_auth_failed(remote_ip: IpAddr, room_id: i32, err: AppError) -> AppError {
+    let room_retry_after = lockout::room_retry_after(room_id);
+    lockout::record(remote_ip, room_id, false);
+    match room_retry_after {
+        Some(retry_after) => AppError::TooManyAttempts(retry_after),
+        None => err,
+    }
+}
+
+/// Checks the user's credentials and, if given, that the user's role grants
+/// `permission`. Failures count towards the lockout of `remote_ip` and the
+/// room.
 pub async fn auth_user(
     room_id: i32,
     shared_key: String,
     user_id: i32,
-    token: u32,
-) -> Result<(Room, Client), Response> {
-    let mut rooms = ROOMS.lock().await;
-
-    if !rooms.contains_key(&room_id) {
-        return Err(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+    token: String,
+    permission: Option<Permission>,
+    remote_ip: IpAddr,
+) -> Result<(Room, Client), AppError> {
+    let room = match ROOMS.lock().await.get(&room_id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    check_user_lockout(remote_ip)?;
 
-    let room: &mut Room = rooms.get_mut(&room_id).unwrap();
-    if !room.auth_shared_key(shared_key.clone()) {
-        return Err(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
+    if !room.auth_shared_key(shared_key.clone()).await {
+        return Err(user_auth_failed(
+            remote_ip,
+            room_id,
+            AppError::InvalidSharedKey,
         ));
     }
 
@@ -83,20 +301,30 @@ pub async fn auth_user(
     let client = clients.get(&user_id).cloned();
     drop(clients);
 
-    if let Some(client) = client {
-        if !client.check_token(token.clone()) {
-            return Err(http::create_response(
-                Body::from(BodyUtil::INVILED_TOKEN),
-                StatusCode::NOT_ACCEPTABLE,
-            ));
+    let client = match client {
+        Some(client) => client,
+        None => {
+            return Err(user_auth_failed(
+                remote_ip,
+                room_id,
+                AppError::UserNotFound(user_id),
+            ))
         }
+    };
+    if !client.check_token(&token) {
+        return Err(user_auth_failed(remote_ip, room_id, AppError::InvalidToken));
+    }
+    // Only a fully passed check clears the failures of the ip
+    lockout::record(remote_ip, room_id, true);
 
-        let room = rooms.get(&room_id).cloned().unwrap();
-        return Ok((room, client));
-    } else {
-        return Err(http::create_response(
-            Body::from(BodyUtil::UNKNOWN_ERROR),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+    if let Some(permission) = permission {
+        if !room.allows(user_id, permission).await {
+            return Err(AppError::permission_denied(format!(
+                "role may not {:?}",
+                permission
+            )));
+        }
     }
+
+    Ok((room, client))
 }
@@ -0,0 +1,163 @@
+use axum::routing::get;
+use axum::{Json, Router};
+use tracing::debug;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+
+use crate::error::{ErrorCode, ErrorJson};
+use crate::event::{RoomEvent, RoomEventEntry};
+use crate::http::response::{Layer, RTCPeerConnectionState, SessionInfo, StreamInfo};
+use crate::property::{Property, PropertyUpdate};
+use crate::role::RolePermissions;
+use crate::room::HostMigrationPolicy;
+use crate::route::room::{MemberInfoJson, RoomInfoJson};
+use crate::route::*;
+
+/// Served without credentials, like `/health`.
+pub fn route() -> Router<AppState> {
+    Router::new().route("/openapi.json", get(openapi_json))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "rust-server-for-multiplayer",
+        description = "Every `/v2` route also exists under its original path, which takes the \
+                       arguments as base64 JSON in a trailing path segment instead, e.g. \
+                       `/room/join/{base64}/`. Room credentials may be left out of `/v2` \
+                       arguments and sent in the `x-room-*` headers instead."
+    ),
+    components(schemas(
+        ErrorJson,
+        ErrorCode,
+        RoomInfoJson,
+        MemberInfoJson,
+        RoomEvent,
+        RoomEventEntry,
+        Property,
+        PropertyUpdate,
+        RolePermissions,
+        HostMigrationPolicy,
+        Layer,
+        StreamInfo,
+        SessionInfo,
+        RTCPeerConnectionState
+    )),
+    modifiers(&AuthSchemes),
+    tags(
+        (name = "room", description = "Rooms, their members, roles and properties"),
+        (name = "stream", description = "WebRTC streams, WHIP and WHEP"),
+        (name = "ws", description = "WebSocket connections"),
+        (name = "server", description = "Health and metrics"),
+        (name = "admin", description = "Every room, for operators")
+    )
+)]
+struct ApiDoc;
+
+/// `Authorization` as checked by `auth::ManyValidate`. Servers without
+/// `[auth]` take requests without it.
+struct AuthSchemes;
+
+impl Modify for AuthSchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("A static token, a credential token or a JWT"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Basic)
+                    .description(Some("An account or a credential with a password"))
+                    .build(),
+            ),
+        );
+        openapi.security = Some(vec![
+            SecurityRequirement::new("bearer", Vec::<String>::new()),
+            SecurityRequirement::new("basic", Vec::<String>::new()),
+            SecurityRequirement::default(),
+        ]);
+    }
+}
+
+/// Every operation answers errors with `ErrorJson`. Applied after the
+/// routes are merged in.
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let response = ResponseBuilder::new()
+            .description("See `ErrorCode` for the status of each code")
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Ref::from_schema_name("ErrorJson"))
+                    .build(),
+            )
+            .build();
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .responses
+            .insert("Error".to_string(), RefOr::T(response));
+
+        for path in openapi.paths.paths.values_mut() {
+            for operation in path.operations.values_mut() {
+                for status in ["4XX", "5XX"] {
+                    operation.responses.responses.insert(
+                        status.to_string(),
+                        RefOr::Ref(Ref::from_response_name("Error")),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The OpenAPI document of every route.
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    let apis = [
+        room::room::Api::openapi(),
+        room::create::Api::openapi(),
+        room::delete::Api::openapi(),
+        room::events::Api::openapi(),
+        room::join::Api::openapi(),
+        room::quick_join::Api::openapi(),
+        room::invite::Api::openapi(),
+        room::exit::Api::openapi(),
+        room::kick::Api::openapi(),
+        room::property::Api::openapi(),
+        room::roster::Api::openapi(),
+        room::role::Api::openapi(),
+        room::token::Api::openapi(),
+        rtc::infos::Api::openapi(),
+        rtc::stream::Api::openapi(),
+        rtc::whip::Api::openapi(),
+        rtc::whep::Api::openapi(),
+        rtc::session::Api::openapi(),
+        ws::Api::openapi(),
+        health::Api::openapi(),
+        metrics::Api::openapi(),
+        admin::Api::openapi(),
+    ];
+    for api in apis {
+        openapi.merge(api);
+    }
+    ErrorResponses.modify(&mut openapi);
+    openapi
+}
+
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    debug!("HTTP GET /openapi.json");
+
+    Json(openapi())
+}
@@ -1,65 +1,183 @@
 use axum::body::Body;
-use axum::extract::{Path, State};
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
 use axum::response::Response;
 use axum::routing::post;
+use axum::Extension;
 use axum::Router;
 use http::StatusCode;
 use log::debug;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::auth::Permissions;
+use crate::config::Config;
+use crate::error::AppError;
 use crate::http;
+use crate::key::RoomKey;
+use crate::property::{PropertyUpdate, RoomProperties};
 use crate::result::Result;
-use crate::room::Room;
+use crate::role::{self, RolePermissions, RoomRoles};
+use crate::room::{
+    generate_invite_code, generate_room_id, HostMigrationPolicy, Room, SPECTATOR_ID_BASE,
+};
 use crate::route::*;
+use crate::webhook::{WebhookAction, WebhookRequest};
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/create/:base64/", post(create_room))
+    Router::new()
+        .route("/room/create/:base64/", post(create_room))
+        .route("/v2/room/create", post(create_room))
 }
 
-#[derive(Serialize, Deserialize)]
-struct RequestJson {
-    name: String,
+#[derive(OpenApi)]
+#[openapi(paths(create_room))]
+pub struct Api;
+
+/// Also used as the template of `/room/quick_join`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestJson {
+    pub name: String,
     capacity: u32,
     needs_host: bool,
+    #[serde(default)]
+    host_migration: HostMigrationPolicy,
     is_public: bool,
+    #[serde(default)]
     shared_key: String,
+    #[serde(default)]
     master_key: String,
     description: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    properties: HashMap<String, PropertyUpdate>,
+    /// Gives the room a short invite code that can be joined with.
+    #[serde(default)]
+    invite_code: bool,
+    /// Custom roles, which may also redefine the built-in ones except `host`.
+    #[serde(default)]
+    roles: HashMap<String, RolePermissions>,
+    /// Role of members nobody has assigned one to, `player` if omitted.
+    #[serde(default)]
+    default_role: Option<String>,
+    /// Spectators that may join on top of `capacity`, none if omitted.
+    #[serde(default)]
+    spectator_capacity: u32,
+    /// Sends OnOpen and OnClose about spectators as well.
+    #[serde(default)]
+    spectator_events: bool,
 }
 
-async fn create_room(
-    State(state): State<AppState>,
-    Path(params): Path<HashMap<String, String>>,
-) -> Result<Response> {
-    debug!("HTTP GET /room/create");
+/// The shared and master key of the room to create, hashed before ROOMS is
+/// locked.
+pub async fn new_room_keys(request: &RequestJson) -> (RoomKey, RoomKey) {
+    tokio::join!(
+        RoomKey::new_blocking(request.shared_key.clone()),
+        RoomKey::new_blocking(request.master_key.clone())
+    )
+}
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
+/// Builds a room with an id and invite code unused in `rooms`, or fails if
+/// the initial properties, the roles or the capacities are invalid. The
+/// caller inserts it into `rooms`, which must stay locked in between, and
+/// into the store.
+pub async fn new_room(
+    request: RequestJson,
+    (shared_key, master_key): (RoomKey, RoomKey),
+    config: Config,
+    rooms: &HashMap<i32, Room>,
+) -> Result<Room> {
+    let mut properties = RoomProperties::default();
+    if properties.apply(request.properties, true).is_none() {
+        return Err(AppError::invalid_request("invalid properties"));
+    }
+    let default_role = request
+        .default_role
+        .unwrap_or_else(|| role::PLAYER.to_string());
+    let mut roles = match RoomRoles::new(request.roles, default_role) {
+        Some(roles) => roles,
+        None => return Err(AppError::invalid_request("invalid roles")),
     };
+    roles.set_spectator_events(request.spectator_events);
+    let max_capacity = SPECTATOR_ID_BASE as u32;
+    if request.capacity > max_capacity || request.spectator_capacity > max_capacity {
+        return Err(AppError::invalid_request(format!(
+            "capacities must not exceed {}",
+            max_capacity
+        )));
+    }
 
-    let mut rooms = ROOMS.lock().await;
-
-    let room_id = utils::unique::generate_unique_i32();
+    let room_id = generate_room_id(rooms);
 
     let room = Room::new(
         room_id,
         request.name.to_string(),
         request.needs_host,
+        request.host_migration,
         request.is_public,
         request.capacity,
-        request.shared_key,
-        request.master_key,
+        request.spectator_capacity,
+        shared_key,
+        master_key,
         request.description,
-        state.config,
+        request.tags,
+        properties,
+        roles,
+        config,
     );
+    if request.invite_code {
+        room.set_invite_code(generate_invite_code(rooms).await)
+            .await;
+    }
+    Ok(room)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/create",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "The created room", body = RoomInfoJson))
+)]
+async fn create_room(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    headers: HeaderMap,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/create");
+
+    if !permissions.can_create_room() {
+        return Err(AppError::permission_denied(
+            "credential may not create rooms",
+        ));
+    }
+
+    let webhook_request = WebhookRequest {
+        name: Some(request.name.clone()),
+        ..WebhookRequest::new(WebhookAction::Create, addr.ip(), &headers)
+    };
+    authorize(&state, webhook_request).await?;
+
+    let keys = new_room_keys(&request).await;
+
+    let mut rooms = ROOMS.lock().await;
+
+    let room = new_room(request, keys, state.config, &rooms).await?;
 
-    let body = serde_json::to_string(&room.info()).unwrap().to_string();
+    let body = serde_json::to_string(&room.info().await)
+        .unwrap()
+        .to_string();
 
-    rooms.insert(room_id, room);
+    state.store.save(room.snapshot().await);
+    rooms.insert(room.id(), room);
 
     return Ok(http::create_response(Body::from(body), StatusCode::OK));
 }
@@ -1,58 +1,83 @@
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::{ConnectInfo, State};
 use axum::response::Response;
 use axum::routing::post;
+use axum::Extension;
 use axum::Router;
 use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::auth::Permissions;
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
 use crate::route::*;
 use crate::ROOMS;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     id: i32,
+    #[serde(default)]
     master_key: String,
 }
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/delete/:base64/", post(delete_room))
+    Router::new()
+        .route("/room/delete/:base64/", post(delete_room))
+        .route("/v2/room/delete", post(delete_room))
 }
 
-async fn delete_room(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[derive(OpenApi)]
+#[openapi(paths(delete_room))]
+pub struct Api;
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/delete",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn delete_room(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
     debug!("HTTP GET /room/delete");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
+    let room = match ROOMS.lock().await.get(&request.id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
     };
 
-    let mut rooms = ROOMS.lock().await;
-
-    if !rooms.contains_key(&request.id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+    if !permissions.admin {
+        check_lockout(addr.ip(), request.id)?;
+        let is_ok = room.auth_master_key(request.master_key.clone()).await;
+        lockout::record(addr.ip(), request.id, is_ok);
+        if !is_ok {
+            return Err(AppError::InvalidMasterKey);
+        }
     }
 
-    let room: &mut Room = rooms.get_mut(&request.id).unwrap();
-    if !room.auth_master_key(request.master_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::REJECTED),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+    let mut rooms = ROOMS.lock().await;
+
+    // Deleted while the key was checked
+    let room: &mut Room = match rooms.get_mut(&request.id) {
+        Some(room) => room,
+        None => return Err(AppError::RoomNotFound),
+    };
 
-    room.all_user_delete().await?;
+    room.close().await?;
     rooms.remove(&request.id);
+    state.store.remove(request.id);
 
     return Ok(http::create_response(
         Body::from(BodyUtil::SUCCEED),
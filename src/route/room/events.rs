@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Path};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Extension;
+use axum::Router;
+use futures_util::stream;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+use utoipa::{IntoParams, OpenApi};
+
+use crate::auth::Permissions;
+use crate::error::AppError;
+use crate::event::{RoomEvent, RoomEventEntry};
+use crate::result::Result;
+use crate::route::*;
+use crate::ROOMS;
+
+pub fn route() -> Router<AppState> {
+    Router::new().route("/room/:room_id/events", get(room_events))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_events))]
+pub struct Api;
+
+/// `EventSource` can not set headers, so the key may come in the query.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RequestJson {
+    #[serde(default)]
+    shared_key: String,
+}
+
+struct Listener {
+    missed: VecDeque<RoomEventEntry>,
+    receiver: broadcast::Receiver<RoomEventEntry>,
+    done: bool,
+}
+
+/// Events of the room as Server-Sent Events.
+///
+/// Each event is a `RoomEventEntry`. Sent with `Last-Event-ID`, the kept
+/// events after it come first. The stream ends with `room_delete`, or when
+/// events were dropped for a slow listener, which then resumes with
+/// `Last-Event-ID`.
+#[utoipa::path(
+    get,
+    path = "/room/{room_id}/events",
+    tag = "room",
+    params(
+        ("room_id" = i32, Path, description = "Room to listen to"),
+        ("last-event-id" = Option<u64>, Header, description = "Id of the last event received"),
+        CredentialHeaders,
+        RequestJson
+    ),
+    responses((status = 200, content_type = "text/event-stream", body = RoomEventEntry))
+)]
+async fn room_events(
+    Path(room_id): Path<i32>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    headers: HeaderMap,
+    QueryArgs(request): QueryArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/events");
+
+    let room = match ROOMS.lock().await.get(&room_id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
+    };
+    if !permissions.admin {
+        check_lockout(addr.ip(), room_id)?;
+        let is_ok = room.auth_shared_key(request.shared_key.clone()).await;
+        lockout::record(addr.ip(), room_id, is_ok);
+        if !is_ok {
+            return Err(AppError::InvalidSharedKey);
+        }
+    }
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    // Subscribed under ROOMS, a room deleted while the key was checked has
+    // already sent its last event
+    let rooms = ROOMS.lock().await;
+    if !rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotFound);
+    }
+    let (missed, receiver) = room.events().subscribe(last_event_id);
+    drop(rooms);
+
+    let listener = Listener {
+        missed: missed.into(),
+        receiver,
+        done: false,
+    };
+    let events = stream::unfold(listener, |mut listener| async move {
+        if listener.done {
+            return None;
+        }
+        let entry = match listener.missed.pop_front() {
+            Some(entry) => entry,
+            None => match listener.receiver.recv().await {
+                Ok(entry) => entry,
+                Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+            },
+        };
+        listener.done = matches!(entry.event, RoomEvent::RoomDelete);
+        let event = Event::default().id(entry.id.to_string()).json_data(&entry);
+        Some((event, listener))
+    });
+
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
@@ -1,5 +1,5 @@
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::ConnectInfo;
 use axum::response::Response;
 use axum::routing::post;
 use axum::Router;
@@ -7,9 +7,11 @@ use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -17,54 +19,76 @@ use crate::route::*;
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/exit/:base64/", post(room_exit))
+    Router::new()
+        .route("/room/exit/:base64/", post(room_exit))
+        .route("/v2/room/exit", post(room_exit))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(room_exit))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
     shared_key: String,
 }
 
-async fn room_exit(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/room/exit",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn room_exit(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
     debug!("HTTP GET /room/exit");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
+    let room = match ROOMS.lock().await.get(&request.room_id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
     };
 
-    let mut rooms = ROOMS.lock().await;
+    check_user_lockout(addr.ip())?;
 
-    if !rooms.contains_key(&request.room_id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
+    if !room.auth_shared_key(request.shared_key.clone()).await {
+        return Err(user_auth_failed(
+            addr.ip(),
+            request.room_id,
+            AppError::InvalidSharedKey,
         ));
     }
 
-    let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
-    if !room.auth_shared_key(request.shared_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+    let mut rooms = ROOMS.lock().await;
 
-    if room
-        .user_delete(request.user_id.clone(), request.token.clone(), true)
-        .await?
-    {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::SUCCEED),
-            StatusCode::OK,
-        ));
-    } else {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::REJECTED),
-            StatusCode::NOT_ACCEPTABLE,
+    // Deleted while the key was checked
+    let room: &mut Room = match rooms.get_mut(&request.room_id) {
+        Some(room) => room,
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    let is_ok = room
+        .user_delete(request.user_id, &request.token, true)
+        .await?;
+    if !is_ok {
+        return Err(user_auth_failed(
+            addr.ip(),
+            request.room_id,
+            AppError::InvalidToken,
         ));
     }
+    lockout::record(addr.ip(), request.room_id, true);
+
+    return Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ));
 }
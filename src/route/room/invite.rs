@@ -0,0 +1,253 @@
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Extension;
+use axum::Router;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddr;
+use tracing::{debug, info};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::auth::Permissions;
+use crate::error::AppError;
+use crate::http;
+use crate::result::Result;
+use crate::room::{find_by_invite_code, generate_invite_code, Room};
+use crate::route::*;
+use crate::webhook::{WebhookAction, WebhookRequest};
+use crate::ROOMS;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/invite/:base64/", post(room_invite))
+        .route("/room/invite/join/:base64/", post(room_invite_join))
+        .route(
+            "/room/invite/regenerate/:base64/",
+            post(room_invite_regenerate),
+        )
+        .route("/v2/room/invite", post(room_invite))
+        .route("/v2/room/invite/join", post(room_invite_join))
+        .route("/v2/room/invite/regenerate", post(room_invite_regenerate))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_invite, room_invite_join, room_invite_regenerate))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct JoinRequestJson {
+    code: String,
+    name: String,
+    #[serde(default)]
+    shared_key: String,
+    #[serde(default)]
+    master_key: String,
+    #[serde(default)]
+    spectator: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct JoinResponseJson {
+    room_id: i32,
+    id: i32,
+    token: String,
+    expires_at: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RegenerateRequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+    /// Lets a backend regenerate the code without being the host.
+    #[serde(default)]
+    master_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RegenerateResponseJson {
+    invite_code: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/invite",
+    tag = "room",
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "The room of the invite code", body = RoomInfoJson))
+)]
+async fn room_invite(RequestArgs(request): RequestArgs<RequestJson>) -> Result<Response> {
+    debug!("HTTP GET /room/invite");
+
+    let rooms = ROOMS.lock().await;
+
+    let room = match find_by_invite_code(&rooms, &request.code).await {
+        Some(room_id) => rooms.get(&room_id).unwrap(),
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    let body = serde_json::to_string(&room.info().await).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/invite/join",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(JoinRequestJson),
+    responses(
+        (status = 200, description = "The room, id and token of the joined user", body = inline(JoinResponseJson))
+    )
+)]
+async fn room_invite_join(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    headers: HeaderMap,
+    RequestArgs(request): RequestArgs<JoinRequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/invite/join");
+
+    let webhook_request = WebhookRequest {
+        name: Some(request.name.clone()),
+        invite_code: Some(request.code.clone()),
+        ..WebhookRequest::new(WebhookAction::Join, addr.ip(), &headers)
+    };
+    let decision = authorize(&state, webhook_request).await?;
+
+    let rooms = ROOMS.lock().await;
+    let room = match find_by_invite_code(&rooms, &request.code).await {
+        Some(room_id) => rooms.get(&room_id).cloned().unwrap(),
+        None => return Err(AppError::RoomNotFound),
+    };
+    drop(rooms);
+
+    if !permissions.can_join_room(room.id()) {
+        return Err(AppError::permission_denied(
+            "credential may not join the room",
+        ));
+    }
+
+    check_lockout(addr.ip(), room.id())?;
+
+    // The keys are checked with ROOMS unlocked, argon2 takes a while
+    if !room.auth_shared_key(request.shared_key.clone()).await {
+        lockout::record(addr.ip(), room.id(), false);
+        return Err(AppError::InvalidSharedKey);
+    }
+
+    let as_host = !request.master_key.is_empty();
+    if as_host && !room.auth_master_key(request.master_key.clone()).await {
+        lockout::record(addr.ip(), room.id(), false);
+        return Err(AppError::InvalidMasterKey);
+    }
+    lockout::record(addr.ip(), room.id(), true);
+
+    let mut rooms = ROOMS.lock().await;
+
+    // Deleted, or the code regenerated, while the keys were checked
+    if find_by_invite_code(&rooms, &request.code).await != Some(room.id()) {
+        return Err(AppError::RoomNotFound);
+    }
+    let room: &mut Room = rooms.get_mut(&room.id()).unwrap();
+
+    let mut user_id = i32::default();
+    let mut token = String::default();
+    if request.spectator {
+        room.join_spectator(request.name.clone(), addr.ip(), &mut user_id, &mut token)
+            .await?;
+    } else {
+        room.join(
+            request.name.clone(),
+            as_host,
+            addr.ip(),
+            &mut user_id,
+            &mut token,
+        )
+        .await?;
+    }
+
+    apply_decision(room, user_id, decision).await;
+
+    let response = JoinResponseJson {
+        room_id: room.id(),
+        id: user_id,
+        token,
+        expires_at: room.token_expires_at(user_id).await,
+    };
+    let body = serde_json::to_string(&response).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/invite/regenerate",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RegenerateRequestJson),
+    responses(
+        (status = 200, description = "The new invite code", body = inline(RegenerateResponseJson))
+    )
+)]
+async fn room_invite_regenerate(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RegenerateRequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/invite/regenerate");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    let mut is_host = room.is_host(request.user_id).await;
+    if !is_host && !request.master_key.is_empty() {
+        check_lockout(addr.ip(), request.room_id)?;
+        is_host = room.auth_master_key(request.master_key).await;
+        lockout::record(addr.ip(), request.room_id, is_host);
+        if !is_host {
+            return Err(AppError::InvalidMasterKey);
+        }
+    }
+    if !is_host {
+        return Err(AppError::permission_denied(
+            "only the host may regenerate the invite code",
+        ));
+    }
+
+    let rooms = ROOMS.lock().await;
+    let invite_code = generate_invite_code(&rooms).await;
+    room.set_invite_code(invite_code.clone()).await;
+    // A room deleted meanwhile must not be written back
+    if rooms.contains_key(&room.id()) {
+        state.store.save(room.snapshot().await);
+    }
+    drop(rooms);
+
+    info!("room : {}, invite code regenerated", room.id());
+
+    let body = serde_json::to_string(&RegenerateResponseJson { invite_code }).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
@@ -1,84 +1,139 @@
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
 use axum::response::Response;
 use axum::routing::post;
+use axum::Extension;
 use axum::Router;
-use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::auth::Permissions;
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
 use crate::route::*;
+use crate::webhook::{WebhookAction, WebhookRequest};
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/join/:base64/", post(room_join))
+    Router::new()
+        .route("/room/join/:base64/", post(room_join))
+        .route("/v2/room/join", post(room_join))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(room_join))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     name: String,
     id: i32,
+    #[serde(default)]
     shared_key: String,
+    #[serde(default)]
     master_key: String,
+    /// Joins as a spectator, which does not take a player slot.
+    #[serde(default)]
+    spectator: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ResponseJson {
     id: i32,
-    token: u32,
+    token: String,
+    expires_at: i64,
 }
 
-async fn room_join(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/room/join",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "The id and token of the joined user", body = inline(ResponseJson))
+    )
+)]
+async fn room_join(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    headers: HeaderMap,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
     debug!("HTTP GET /room/join");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
+    if !permissions.can_join_room(request.id) {
+        return Err(AppError::permission_denied(
+            "credential may not join the room",
+        ));
+    }
+
+    // Asked before ROOMS is locked, the service may take a while to answer
+    let webhook_request = WebhookRequest {
+        room_id: Some(request.id),
+        name: Some(request.name.clone()),
+        ..WebhookRequest::new(WebhookAction::Join, addr.ip(), &headers)
     };
+    let decision = authorize(&state, webhook_request).await?;
 
-    let mut rooms = ROOMS.lock().await;
+    let room = match ROOMS.lock().await.get(&request.id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
+    };
 
-    if !rooms.contains_key(&request.id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+    check_lockout(addr.ip(), request.id)?;
+
+    // The keys are checked with ROOMS unlocked, argon2 takes a while
+    if !room.auth_shared_key(request.shared_key.clone()).await {
+        lockout::record(addr.ip(), request.id, false);
+        return Err(AppError::InvalidSharedKey);
     }
 
-    let room: &mut Room = rooms.get_mut(&request.id).unwrap();
-    if !room.auth_shared_key(request.shared_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+    let as_host = !request.master_key.is_empty();
+    if as_host && !room.auth_master_key(request.master_key.clone()).await {
+        lockout::record(addr.ip(), request.id, false);
+        return Err(AppError::InvalidMasterKey);
     }
+    lockout::record(addr.ip(), request.id, true);
+
+    let mut rooms = ROOMS.lock().await;
+
+    // Deleted while the keys were checked
+    let room: &mut Room = match rooms.get_mut(&request.id) {
+        Some(room) => room,
+        None => return Err(AppError::RoomNotFound),
+    };
 
     let mut user_id = i32::default();
-    let mut token = u32::default();
-    if !room
-        .join(
+    let mut token = String::default();
+    if request.spectator {
+        room.join_spectator(request.name.clone(), addr.ip(), &mut user_id, &mut token)
+            .await?;
+    } else {
+        room.join(
             request.name.clone(),
-            request.master_key.clone(),
+            as_host,
+            addr.ip(),
             &mut user_id,
             &mut token,
         )
-        .await?
-    {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::REJECTED),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        .await?;
     }
 
+    apply_decision(room, user_id, decision).await;
+
     let response = ResponseJson {
         id: user_id.clone(),
         token: token.clone(),
+        expires_at: room.token_expires_at(user_id).await,
     };
     let body = serde_json::to_string(&response).unwrap().to_string();
 
@@ -0,0 +1,105 @@
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Extension;
+use axum::Router;
+use http::BodyUtil;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddr;
+use tracing::{debug, info};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::auth::Permissions;
+use crate::error::AppError;
+use crate::http;
+use crate::result::Result;
+use crate::room::Room;
+use crate::route::*;
+use crate::ROOMS;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/kick/:base64/", post(room_kick))
+        .route("/v2/room/kick", post(room_kick))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_kick))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    master_key: String,
+    #[serde(default)]
+    ban: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/kick",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn room_kick(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/kick");
+
+    let room = match ROOMS.lock().await.get(&request.room_id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    if !permissions.admin {
+        check_lockout(addr.ip(), request.room_id)?;
+        let is_ok = room.auth_master_key(request.master_key.clone()).await;
+        lockout::record(addr.ip(), request.room_id, is_ok);
+        if !is_ok {
+            return Err(AppError::InvalidMasterKey);
+        }
+    }
+
+    let mut rooms = ROOMS.lock().await;
+
+    // Deleted while the key was checked
+    let room: &mut Room = match rooms.get_mut(&request.room_id) {
+        Some(room) => room,
+        None => return Err(AppError::RoomNotFound),
+    };
+
+    let client_map = room.client_map();
+    let clients = client_map.read().await;
+    let client = clients.get(&request.user_id).cloned();
+    drop(clients);
+
+    let client = match client {
+        Some(client) => client,
+        None => return Err(AppError::UserNotFound(request.user_id)),
+    };
+
+    if request.ban {
+        room.ban(client.name(), client.remote_ip()).await;
+    }
+
+    room.user_delete(request.user_id, "", false).await?;
+
+    info!(
+        "room : {}, kick user : {} (ban : {})",
+        request.room_id, request.user_id, request.ban
+    );
+
+    return Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ));
+}
@@ -1,15 +1,46 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
 
 pub mod create;
 pub mod delete;
+pub mod events;
 pub mod exit;
+pub mod invite;
 pub mod join;
+pub mod kick;
+pub mod property;
+pub mod quick_join;
+pub mod role;
 pub mod room;
+pub mod roster;
+pub mod token;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RoomInfoJson {
     pub id: i32,
     pub name: String,
     pub capacity: u32,
     pub description: String,
+    pub occupancy: u32,
+    pub spectator_capacity: u32,
+    pub spectators: u32,
+    pub has_host: bool,
+    pub create_time: i64,
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invite_code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MemberInfoJson {
+    pub id: i32,
+    pub name: String,
+    pub join_time: i64,
+    pub role: String,
+    pub spectator: bool,
+    pub streams: Vec<String>,
+    pub properties: HashMap<String, Value>,
 }
@@ -0,0 +1,145 @@
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::response::Response;
+use axum::routing::post;
+use axum::Router;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::AppError;
+use crate::http;
+use crate::property::PropertyUpdate;
+use crate::result::Result;
+use crate::role::Permission;
+use crate::route::*;
+use crate::ROOMS;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/property/:base64/", post(room_property))
+        .route("/room/property/set/:base64/", post(room_property_set))
+        .route("/v2/room/property", post(room_property))
+        .route("/v2/room/property/set", post(room_property_set))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_property, room_property_set))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct SetRequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+    /// Lets a backend write host-only keys without being the host.
+    #[serde(default)]
+    master_key: String,
+    properties: HashMap<String, PropertyUpdate>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/property",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "Properties of the room", body = HashMap<String, Property>)
+    )
+)]
+async fn room_property(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/property");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    let body = serde_json::to_string(&room.properties().await).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/property/set",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(SetRequestJson),
+    responses(
+        (status = 200, description = "Properties of the room after the update", body = HashMap<String, Property>)
+    )
+)]
+async fn room_property_set(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<SetRequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/property/set");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        Some(Permission::SetProperties),
+        addr.ip(),
+    )
+    .await?;
+
+    let mut is_host = room.is_host(request.user_id).await;
+    if !is_host && !request.master_key.is_empty() {
+        check_lockout(addr.ip(), request.room_id)?;
+        is_host = room.auth_master_key(request.master_key).await;
+        lockout::record(addr.ip(), request.room_id, is_host);
+        if !is_host {
+            return Err(AppError::InvalidMasterKey);
+        }
+    }
+
+    if !room
+        .set_properties(request.user_id, request.properties, is_host)
+        .await
+    {
+        return Err(AppError::conflict(
+            "a version check or a host-only key rejected the updates",
+        ));
+    }
+
+    let rooms = ROOMS.lock().await;
+    // A room deleted meanwhile must not be written back
+    if rooms.contains_key(&request.room_id) {
+        state.store.save(room.snapshot().await);
+    }
+    drop(rooms);
+
+    let body = serde_json::to_string(&room.properties().await).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
@@ -0,0 +1,234 @@
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Extension;
+use axum::Router;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::{debug, info};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::auth::Permissions;
+use crate::error::{AppError, ErrorCode};
+use crate::http;
+use crate::result::Result;
+use crate::room::Room;
+use crate::route::room::create::{self, new_room, new_room_keys};
+use crate::route::room::RoomInfoJson;
+use crate::route::*;
+use crate::webhook::{WebhookAction, WebhookRequest};
+use crate::ROOMS;
+
+/// Matching rooms whose shared key is checked per request, each check
+/// counting as an attempt towards the lockout. Tags and properties narrow
+/// the rooms down beforehand.
+const MAX_KEY_CHECKS: usize = 3;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/quick_join/:base64/", post(room_quick_join))
+        .route("/v2/room/quick_join", post(room_quick_join))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_quick_join))]
+pub struct Api;
+
+/// Region, skill bucket and similar criteria are expressed as tags or room
+/// properties, e.g. `"properties": {"region": "eu"}`.
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    name: String,
+    #[serde(default)]
+    shared_key: String,
+    /// Only rooms carrying every listed tag.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Only rooms whose properties have exactly these values.
+    #[serde(default)]
+    properties: HashMap<String, Value>,
+    /// Room created when nothing matches. Without it the request is rejected.
+    #[serde(default)]
+    #[schema(inline)]
+    template: Option<create::RequestJson>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ResponseJson {
+    room: RoomInfoJson,
+    id: i32,
+    token: String,
+    expires_at: i64,
+    created: bool,
+}
+
+/// The shared key is checked afterwards, with ROOMS unlocked.
+async fn matches(room: &Room, request: &RequestJson) -> bool {
+    if !room.is_public() {
+        return false;
+    }
+    let tags = room.tags();
+    if !request.tags.iter().all(|tag| tags.contains(tag)) {
+        return false;
+    }
+    if !request.properties.is_empty() {
+        let properties = room.properties().await;
+        let is_match = request.properties.iter().all(|(key, value)| {
+            properties
+                .get(key)
+                .is_some_and(|property| property.value == *value)
+        });
+        if !is_match {
+            return false;
+        }
+    }
+    room.free_slots().await > 0
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/quick_join",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "The joined, or created, room with the id and token of the user", body = inline(ResponseJson))
+    )
+)]
+async fn room_quick_join(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    headers: HeaderMap,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/quick_join");
+
+    let webhook_request = WebhookRequest {
+        name: Some(request.name.clone()),
+        ..WebhookRequest::new(WebhookAction::Join, addr.ip(), &headers)
+    };
+    let decision = authorize(&state, webhook_request).await?;
+    // Creating from the template is asked for, and its keys hashed, up front
+    // as well, so that neither is waited on with ROOMS locked.
+    let template_keys = match &request.template {
+        Some(template) if permissions.can_create_room() => {
+            let webhook_request = WebhookRequest {
+                name: Some(template.name.clone()),
+                ..WebhookRequest::new(WebhookAction::Create, addr.ip(), &headers)
+            };
+            match authorize(&state, webhook_request).await {
+                Ok(_) => Some(new_room_keys(template).await),
+                Err(_) => None,
+            }
+        }
+        _ => None,
+    };
+
+    // Rooms the request fits, fullest first and the oldest one on a tie.
+    // Their shared keys are checked afterwards with ROOMS unlocked.
+    let rooms = ROOMS.lock().await;
+    let mut candidates = vec![];
+    for room in rooms.values() {
+        if permissions.can_join_room(room.id()) && matches(room, &request).await {
+            candidates.push((room.occupancy().await, room.create_time(), room.clone()));
+        }
+    }
+    drop(rooms);
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let mut user_id = i32::default();
+    let mut token = String::default();
+    let mut locked = None;
+    for (_, create_time, candidate) in candidates.into_iter().take(MAX_KEY_CHECKS) {
+        let room_id = candidate.id();
+        if let Err(err) = check_lockout(addr.ip(), room_id) {
+            locked = Some(err);
+            continue;
+        }
+        let is_ok = candidate.auth_shared_key(request.shared_key.clone()).await;
+        lockout::record(addr.ip(), room_id, is_ok);
+        if !is_ok {
+            continue;
+        }
+
+        let mut rooms = ROOMS.lock().await;
+        // Deleted, and its id maybe reused, while the key was checked
+        let room: &mut Room = match rooms.get_mut(&room_id) {
+            Some(room) if room.create_time() == create_time => room,
+            _ => continue,
+        };
+        let joined = room
+            .join(
+                request.name.clone(),
+                false,
+                addr.ip(),
+                &mut user_id,
+                &mut token,
+            )
+            .await;
+        // A room the user is banned from or that just filled up is skipped
+        match joined {
+            Ok(()) => {}
+            Err(err) if err.code() == ErrorCode::Internal => return Err(err),
+            Err(_) => continue,
+        }
+
+        apply_decision(room, user_id, decision).await;
+
+        let response = ResponseJson {
+            room: room.info().await,
+            id: user_id,
+            token,
+            expires_at: room.token_expires_at(user_id).await,
+            created: false,
+        };
+        let body = serde_json::to_string(&response).unwrap();
+        return Ok(http::create_response(Body::from(body), StatusCode::OK));
+    }
+
+    let mut rooms = ROOMS.lock().await;
+
+    let mut room = match (request.template, template_keys) {
+        (Some(template), Some(keys)) => new_room(template, keys, state.config, &rooms).await?,
+        (Some(_), None) => {
+            return Err(AppError::permission_denied(
+                "credential may not create rooms",
+            ))
+        }
+        (None, _) => return Err(locked.unwrap_or(AppError::RoomNotFound)),
+    };
+
+    room.join(
+        request.name.clone(),
+        false,
+        addr.ip(),
+        &mut user_id,
+        &mut token,
+    )
+    .await?;
+
+    state.store.save(room.snapshot().await);
+
+    info!("room : {}, created by quick join", room.id());
+
+    apply_decision(&room, user_id, decision).await;
+
+    let response = ResponseJson {
+        room: room.info().await,
+        id: user_id,
+        token,
+        expires_at: room.token_expires_at(user_id).await,
+        created: true,
+    };
+    rooms.insert(room.id(), room);
+    let body = serde_json::to_string(&response).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
@@ -0,0 +1,147 @@
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Router;
+use http::BodyUtil;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddr;
+use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::AppError;
+use crate::http;
+use crate::result::Result;
+use crate::role::{Permission, RolePermissions};
+use crate::route::*;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/role/:base64/", post(room_role))
+        .route("/room/role/set/:base64/", post(room_role_set))
+        .route("/v2/room/role", post(room_role))
+        .route("/v2/room/role/set", post(room_role_set))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_role, room_role_set))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ResponseJson {
+    role: String,
+    permissions: RolePermissions,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct SetRequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+    /// Lets a user without the `assign_roles` permission assign roles.
+    #[serde(default)]
+    master_key: String,
+    target_id: i32,
+    role: String,
+}
+
+/// Role and permissions of the requesting user.
+#[utoipa::path(
+    post,
+    path = "/v2/room/role",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "Role and permissions of the user", body = inline(ResponseJson))
+    )
+)]
+async fn room_role(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/role");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    let response = ResponseJson {
+        role: room.role(request.user_id).await,
+        permissions: room.permissions(request.user_id).await,
+    };
+    let body = serde_json::to_string(&response).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/role/set",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(SetRequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn room_role_set(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<SetRequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/role/set");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    let mut may_assign = room.allows(request.user_id, Permission::AssignRoles).await;
+    if !may_assign && !request.master_key.is_empty() {
+        check_lockout(addr.ip(), request.room_id)?;
+        may_assign = room.auth_master_key(request.master_key).await;
+        lockout::record(addr.ip(), request.room_id, may_assign);
+        if !may_assign {
+            return Err(AppError::InvalidMasterKey);
+        }
+    }
+    if !may_assign {
+        return Err(AppError::permission_denied("role may not AssignRoles"));
+    }
+
+    if room.client(request.target_id).await.is_none() {
+        return Err(AppError::UserNotFound(request.target_id));
+    }
+    if !room.set_role(request.target_id, request.role).await {
+        return Err(AppError::invalid_request("role can not be assigned"));
+    }
+
+    return Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ));
+}
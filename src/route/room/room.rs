@@ -1,15 +1,17 @@
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::ConnectInfo;
 use axum::response::Response;
-use axum::routing::post;
+use axum::routing::{get, post};
 use axum::Router;
-use http::BodyUtil;
+use axum_extra::extract::Query;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing::debug;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -19,68 +21,182 @@ use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
     Router::new()
-        .route("/room", post(room))
+        .route("/room", get(room_legacy).post(room_legacy))
         .merge(Router::new().route("/room/:base64/", post(room_specific)))
+        .route("/v2/room", get(room).post(room))
+        .route("/v2/room/info", post(room_specific))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(room, room_specific))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     id: i32,
+    #[serde(default)]
     shared_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ResponseJson {
     infos: Vec<RoomInfoJson>,
+    total: usize,
 }
 
-async fn room() -> Result<Response> {
-    debug!("HTTP GET /room");
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortKey {
+    #[default]
+    CreateTime,
+    Name,
+    Occupancy,
+    FreeSlots,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct QueryJson {
+    /// Only rooms a player can still join without the master key.
+    #[serde(default)]
+    has_free_slots: bool,
+    /// Only rooms carrying every listed tag (`tag=a&tag=b`).
+    #[serde(default)]
+    tag: Vec<String>,
+    /// Case-insensitive substring of the room name.
+    name: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    sort: SortKey,
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/room",
+    tag = "room",
+    params(QueryJson),
+    responses((status = 200, body = inline(ResponseJson)))
+)]
+async fn room(Query(query): Query<QueryJson>) -> Result<Response> {
+    list_rooms(query, Some(DEFAULT_LIMIT)).await
+}
 
-    let rooms = ROOMS.lock().await;
+/// The list from before paging, which stays complete unless `limit` is given.
+async fn room_legacy(Query(query): Query<QueryJson>) -> Result<Response> {
+    list_rooms(query, None).await
+}
 
-    let mut response = ResponseJson { infos: Vec::new() };
+async fn list_rooms(query: QueryJson, default_limit: Option<usize>) -> Result<Response> {
+    debug!("HTTP GET /room");
 
-    for (_room_id, room) in rooms.iter() {
-        if room.is_public() {
-            response.infos.push(room.info());
+    let rooms: Vec<Room> = ROOMS
+        .lock()
+        .await
+        .values()
+        .filter(|room| room.is_public())
+        .cloned()
+        .collect();
+
+    let name = query.name.as_ref().map(|name| name.to_lowercase());
+    let mut infos = vec![];
+    for room in rooms.iter() {
+        if query.has_free_slots && room.free_slots().await == 0 {
+            continue;
+        }
+        if !query.tag.iter().all(|tag| room.tags().contains(tag)) {
+            continue;
         }
+        if let Some(name) = &name {
+            if !room.name().to_lowercase().contains(name) {
+                continue;
+            }
+        }
+        infos.push((room.info().await, room.free_slots().await));
     }
 
+    infos.sort_by(|(a, a_free), (b, b_free)| {
+        let ordering = match query.sort {
+            SortKey::CreateTime => a.create_time.cmp(&b.create_time),
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Occupancy => a.occupancy.cmp(&b.occupancy),
+            SortKey::FreeSlots => a_free.cmp(b_free),
+        }
+        .then(a.id.cmp(&b.id));
+        match query.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    });
+
+    let total = infos.len();
+    let limit = match query.limit.or(default_limit) {
+        Some(limit) => limit.min(MAX_LIMIT),
+        None => usize::MAX,
+    };
+    let response = ResponseJson {
+        infos: infos
+            .into_iter()
+            .skip(query.offset)
+            .take(limit)
+            .map(|(info, _)| info)
+            .collect(),
+        total,
+    };
+
     return Ok(http::create_response(
         Body::from(serde_json::to_string(&response).unwrap()),
         StatusCode::OK,
     ));
 }
 
-async fn room_specific(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/room/info",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, body = inline(ResponseJson)))
+)]
+async fn room_specific(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
     println!("HTTP GET /room");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
+    let room = match ROOMS.lock().await.get(&request.id) {
+        Some(room) => room.clone(),
+        None => return Err(AppError::RoomNotFound),
     };
 
-    let mut rooms = ROOMS.lock().await;
-
-    if !rooms.contains_key(&request.id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+    check_lockout(addr.ip(), request.id)?;
 
-    let room: &mut Room = rooms.get_mut(&request.id).unwrap();
-    if !room.auth_shared_key(request.shared_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+    let is_ok = room.auth_shared_key(request.shared_key.clone()).await;
+    lockout::record(addr.ip(), request.id, is_ok);
+    if !is_ok {
+        return Err(AppError::InvalidSharedKey);
     }
 
-    let mut response = ResponseJson { infos: Vec::new() };
-
-    response.infos.append(&mut vec![room.info()]);
+    let response = ResponseJson {
+        infos: vec![room.info().await],
+        total: 1,
+    };
 
     return Ok(http::create_response(
         Body::from(serde_json::to_string(&response).unwrap()),
@@ -0,0 +1,132 @@
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Router;
+use http::BodyUtil;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::AppError;
+use crate::http;
+use crate::result::Result;
+use crate::route::room::MemberInfoJson;
+use crate::route::*;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/roster/:base64/", post(room_roster))
+        .route("/room/user/property/set/:base64/", post(user_property_set))
+        .route("/v2/room/roster", post(room_roster))
+        .route("/v2/room/user/property/set", post(user_property_set))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(room_roster, user_property_set))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RosterJson {
+    members: Vec<MemberInfoJson>,
+    spectators: Vec<MemberInfoJson>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct SetRequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+    properties: HashMap<String, Option<Value>>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/roster",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "Members and spectators of the room", body = inline(RosterJson))
+    )
+)]
+async fn room_roster(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/roster");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    let response = RosterJson {
+        members: room.roster(false).await,
+        spectators: room.roster(true).await,
+    };
+    let body = serde_json::to_string(&response).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/room/user/property/set",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(SetRequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn user_property_set(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<SetRequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/user/property/set");
+
+    // Users can only write their own properties, which auth_user guarantees.
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    if !room
+        .set_user_properties(request.user_id, request.properties)
+        .await
+    {
+        return Err(AppError::UserNotFound(request.user_id));
+    }
+
+    return Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ));
+}
@@ -0,0 +1,80 @@
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Router;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddr;
+use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::AppError;
+use crate::http;
+use crate::result::Result;
+use crate::route::*;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/token/refresh/:base64/", post(token_refresh))
+        .route("/v2/room/token/refresh", post(token_refresh))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(token_refresh))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ResponseJson {
+    token: String,
+    expires_at: i64,
+}
+
+/// Exchanges a valid token for a new one. The old token stops working
+/// immediately, connections opened with it stay open.
+#[utoipa::path(
+    post,
+    path = "/v2/room/token/refresh",
+    tag = "room",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "A new token, the old one stops working", body = inline(ResponseJson))
+    )
+)]
+async fn token_refresh(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /room/token/refresh");
+
+    let (room, _) = auth_user(
+        request.room_id,
+        request.shared_key,
+        request.user_id,
+        request.token,
+        None,
+        addr.ip(),
+    )
+    .await?;
+
+    let (token, expires_at) = match room.refresh_token(request.user_id).await {
+        Some(result) => result,
+        None => return Err(AppError::UserNotFound(request.user_id)),
+    };
+
+    let body = serde_json::to_string(&ResponseJson { token, expires_at }).unwrap();
+
+    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+}
@@ -1,58 +1,67 @@
-use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::ConnectInfo;
 use axum::response::Response;
 use axum::routing::post;
 use axum::Router;
 use http::response::StreamInfo;
-use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::route::*;
 use crate::AppState;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
     shared_key: String,
 }
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/stream/infos/:base64/", post(infos))
+    Router::new()
+        .route("/stream/infos/:base64/", post(infos))
+        .route("/v2/stream/infos", post(infos))
 }
 
-async fn infos(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
-    debug!("HTTP GET /stream/infos");
+#[derive(OpenApi)]
+#[openapi(paths(infos))]
+pub struct Api;
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+#[utoipa::path(
+    post,
+    path = "/v2/stream/infos",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Streams of the user", body = Vec<StreamInfo>))
+)]
+async fn infos(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /stream/infos");
 
-    let (room, client) = match auth_user(
+    let (room, client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
-        request.token,
+        request.token.clone(),
+        None,
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
-    if !client.check_token(request.token.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_TOKEN),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+    if !client.check_token(&request.token) {
+        return Err(AppError::InvalidToken);
     }
 
     let streams = client.get_streams().await;
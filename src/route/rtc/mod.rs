@@ -1,4 +1,235 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::ws::{Message, WebSocket};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::Response;
+use serde::Deserialize;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, error};
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::config::IceServer;
+use crate::error::AppError;
+use crate::forward::rtc::client::TokenDigest;
+use crate::http::StatusCode;
+use crate::result::Result;
+use crate::rtc::Forwarder;
+
 pub mod infos;
+pub mod session;
 pub mod stream;
 pub mod whep;
 pub mod whip;
+
+#[derive(Deserialize)]
+struct OfferJson {
+    sdp: String,
+}
+
+/// Offer of a WHIP or WHEP connection that left it out of the request: the
+/// `sdp` of the first message, as in the answer sent back.
+pub async fn receive_offer(socket: &mut WebSocket) -> Option<String> {
+    match socket.recv().await? {
+        Ok(Message::Text(text)) => match serde_json::from_str::<OfferJson>(&text) {
+            Ok(offer) => Some(offer.sdp),
+            Err(err) => {
+                debug!("offer message err: {}", err);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Gives a stream that nobody publishes a publisher inside the server, so
+/// that it can be subscribed to, e.g. for its data channel.
+pub async fn virtual_publish(
+    forwarder: &Forwarder,
+    stream: String,
+    id: u32,
+    token: TokenDigest,
+) -> Result<()> {
+    let (tx0, mut rx) = mpsc::channel::<(u8, String)>(32);
+    let tx1 = tx0.clone();
+    let caches0: Arc<RwLock<Vec<String>>> = Default::default();
+    let caches1: Arc<RwLock<Vec<String>>> = Default::default();
+    let caches0c = Arc::clone(&caches0);
+    let caches1c = Arc::clone(&caches1);
+    let handle = tokio::spawn(async move {
+        let mut c1 = caches1c.write().await;
+        let mut c0 = caches0c.write().await;
+        while let Some(message) = rx.recv().await {
+            match message.0 {
+                0 => {
+                    c1.push(message.1);
+                }
+                1 => {
+                    c0.push(message.1);
+                }
+                _ => todo!(),
+            };
+
+            if !c0.is_empty() && !c1.is_empty() {
+                break;
+            }
+        }
+    });
+    let (peer0, sdp, _session) = forwarder
+        .virtual_publish(
+            stream.clone(),
+            Box::new(move |candidate: Option<RTCIceCandidate>| {
+                let candidate = candidate.clone();
+                let tx0 = tx0.clone();
+                if let Some(candidate) = candidate {
+                    return Box::pin(async move {
+                        let c = candidate.to_json().unwrap().candidate;
+                        if let Err(_err) = tx0.clone().send((0, c.clone())).await {}
+                    });
+                }
+                Box::pin(async {})
+            }),
+        )
+        .await?;
+    let (peer1, answer, _session) = forwarder
+        .publish(
+            stream.clone(),
+            id,
+            token,
+            sdp,
+            Box::new(move |candidate: Option<RTCIceCandidate>| {
+                let candidate = candidate.clone();
+                let tx1 = tx1.clone();
+                if let Some(candidate) = candidate {
+                    return Box::pin(async move {
+                        let c = candidate.to_json().unwrap().candidate;
+                        if let Err(_err) = tx1.clone().send((1, c.clone())).await {}
+                    });
+                }
+                Box::pin(async {})
+            }),
+            Box::new(move || Box::pin(async {})),
+        )
+        .await?;
+    peer0.set_remote_description(answer).await?;
+
+    let _ = handle.await;
+
+    let caches0 = caches0.read().await;
+    let caches0 = caches0.iter();
+    for candidate in caches0 {
+        debug!("[vhost] peer0 add ice-candidate: {}", candidate);
+        if let Err(err) = peer0
+            .add_ice_candidate(RTCIceCandidateInit {
+                candidate: candidate.to_string(),
+                ..Default::default()
+            })
+            .await
+        {
+            error!("{}", err);
+        }
+    }
+
+    let caches1 = caches1.read().await;
+    let caches1 = caches1.iter();
+    for candidate in caches1 {
+        debug!("[vhost] peer1 add ice-candidate: {}", candidate);
+        if let Err(err) = peer1
+            .add_ice_candidate(RTCIceCandidateInit {
+                candidate: candidate.to_string(),
+                ..Default::default()
+            })
+            .await
+        {
+            error!("{}", err);
+        }
+    }
+    Ok(())
+}
+
+/// How long a WHIP or WHEP answer waits for the server's ICE candidates.
+const GATHER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The offer of a WHIP or WHEP request, whose body must be
+/// `application/sdp`.
+pub fn sdp_offer(headers: &HeaderMap, body: String) -> Result<RTCSessionDescription> {
+    check_content_type(headers, "application/sdp")?;
+    RTCSessionDescription::offer(body).map_err(AppError::invalid_request)
+}
+
+pub fn check_content_type(headers: &HeaderMap, content_type: &str) -> Result<()> {
+    let value = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if value.split(';').next().unwrap_or("").trim() != content_type {
+        return Err(AppError::invalid_request(format!(
+            "content type must be {}",
+            content_type
+        )));
+    }
+    Ok(())
+}
+
+/// Waits until the peer has gathered its ICE candidates, or for at most
+/// `GATHER_TIMEOUT`, and gives its local description with them. Candidates
+/// can not be trickled back over HTTP, so answers carry all of them.
+pub async fn gathered_description(peer: &Arc<RTCPeerConnection>) -> Result<RTCSessionDescription> {
+    let mut gather_complete = peer.gathering_complete_promise().await;
+    if tokio::time::timeout(GATHER_TIMEOUT, gather_complete.recv())
+        .await
+        .is_err()
+    {
+        debug!("ice gathering timeout, answer with the candidates so far");
+    }
+    let description = peer
+        .local_description()
+        .await
+        .ok_or(anyhow::anyhow!("failed to get local description"))?;
+    Ok(description)
+}
+
+/// 201 of a WHIP or WHEP offer, with the session url in `Location` and the
+/// ICE servers in `Link` headers.
+pub fn created_response(
+    ice_servers: &[IceServer],
+    location: String,
+    answer: RTCSessionDescription,
+) -> Result<Response> {
+    let mut response = Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::CONTENT_TYPE, "application/sdp")
+        .header(header::LOCATION, location);
+    for link in link_header(ice_servers) {
+        response = response.header(header::LINK, link);
+    }
+    Ok(response.body(Body::from(answer.sdp))?)
+}
+
+/// `Link` header values of the ICE servers, as given in RFC 9725.
+fn link_header(ice_servers: &[IceServer]) -> Vec<HeaderValue> {
+    let mut links = vec![];
+    for ice_server in ice_servers.iter() {
+        let mut params = String::new();
+        if !ice_server.username.is_empty() {
+            let credential_type = match ice_server.credential_type.is_empty() {
+                true => "password",
+                false => ice_server.credential_type.as_str(),
+            };
+            params = format!(
+                "; username=\"{}\"; credential=\"{}\"; credential-type=\"{}\"",
+                ice_server.username, ice_server.credential, credential_type
+            );
+        }
+        for url in ice_server.urls.iter() {
+            match HeaderValue::from_str(&format!("<{}>; rel=\"ice-server\"{}", url, params)) {
+                Ok(link) => links.push(link),
+                Err(err) => error!("ice server {} can not be a link header: {}", url, err),
+            }
+        }
+    }
+    links
+}
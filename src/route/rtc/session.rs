@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::Path;
+use axum::http::{header, HeaderMap};
+use axum::response::Response;
+use axum::routing::patch;
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::debug;
+use utoipa::OpenApi;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_gathering_state::RTCIceGatheringState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::error::AppError;
+use crate::http;
+use crate::http::StatusCode;
+use crate::result::Result;
+use crate::room::Room;
+use crate::route::rtc::{check_content_type, gathered_description};
+use crate::route::*;
+use crate::ROOMS;
+
+const SDP_FRAGMENT: &str = "application/trickle-ice-sdpfrag";
+
+/// Session urls of the WHIP and WHEP `POST` routes. The url carries a
+/// secret only handed out in `Location`, so knowing the url stands in for the
+/// room credentials, as WHIP clients only keep the `Authorization` header.
+pub fn route() -> Router<AppState> {
+    Router::new().route(
+        "/v2/stream/session/:room_id/:session/:secret",
+        patch(patch_session).delete(delete_session),
+    )
+}
+
+lazy_static! {
+    /// Key of the session secrets. Sessions do not outlive the process, so a
+    /// new one is made on every start.
+    static ref SESSION_KEY: [u8; 32] = rand::random();
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(patch_session, delete_session))]
+pub struct Api;
+
+fn session_mac(room_id: i32, session: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(SESSION_KEY.as_slice()).unwrap();
+    mac.update(format!("{}/{}", room_id, session).as_bytes());
+    mac
+}
+
+/// The session id alone is no secret, `infos` and room events show it.
+pub fn session_url(room_id: i32, session: &str) -> String {
+    let secret = session_mac(room_id, session).finalize().into_bytes();
+    format!(
+        "/v2/stream/session/{}/{}/{}",
+        room_id,
+        session,
+        URL_SAFE_NO_PAD.encode(secret)
+    )
+}
+
+/// Compared in constant time. A wrong secret looks like an unknown session.
+fn check_secret(room_id: i32, session: &str, secret: &str) -> Result<()> {
+    let secret = URL_SAFE_NO_PAD
+        .decode(secret)
+        .map_err(|_| AppError::SessionNotFound)?;
+    session_mac(room_id, session)
+        .verify_slice(&secret)
+        .map_err(|_| AppError::SessionNotFound)
+}
+
+/// Trickled candidates, and the ICE credentials of an ICE restart, of a
+/// `PATCH` as in RFC 8840.
+#[derive(Default)]
+struct SdpFragment {
+    ice_ufrag: Option<String>,
+    ice_pwd: Option<String>,
+    candidates: Vec<RTCIceCandidateInit>,
+}
+
+impl SdpFragment {
+    fn parse(body: &str) -> Self {
+        let mut fragment = SdpFragment::default();
+        let mut mid = None;
+        let mut mline_index: Option<u16> = None;
+        for line in body.lines().map(|line| line.trim()) {
+            if let Some(ice_ufrag) = line.strip_prefix("a=ice-ufrag:") {
+                fragment.ice_ufrag = Some(ice_ufrag.to_string());
+            } else if let Some(ice_pwd) = line.strip_prefix("a=ice-pwd:") {
+                fragment.ice_pwd = Some(ice_pwd.to_string());
+            } else if line.starts_with("m=") {
+                mline_index = Some(mline_index.map_or(0, |index| index + 1));
+                mid = None;
+            } else if let Some(value) = line.strip_prefix("a=mid:") {
+                mid = Some(value.to_string());
+            } else if line.starts_with("a=candidate:") {
+                fragment.candidates.push(RTCIceCandidateInit {
+                    candidate: line[2..].to_string(),
+                    sdp_mid: mid.clone(),
+                    sdp_mline_index: mline_index,
+                    username_fragment: fragment.ice_ufrag.clone(),
+                });
+            }
+        }
+        fragment
+    }
+}
+
+async fn find_room(room_id: i32) -> Result<Room> {
+    let rooms = ROOMS.lock().await;
+    match rooms.get(&room_id) {
+        Some(room) => Ok(room.clone()),
+        None => Err(AppError::RoomNotFound),
+    }
+}
+
+async fn session_peer(room_id: i32, session: String) -> Result<Arc<RTCPeerConnection>> {
+    let room = find_room(room_id).await?;
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    forwarder.session_peer(session).await
+}
+
+fn ice_ufrag(sdp: &str) -> Option<&str> {
+    sdp.lines()
+        .find_map(|line| line.trim().strip_prefix("a=ice-ufrag:"))
+}
+
+/// Answers a re-offer of the remote description with the new ICE
+/// credentials, which makes the peer restart ICE. Gives the fragment of the
+/// answer with the server's new credentials and candidates.
+async fn ice_restart(
+    peer: &Arc<RTCPeerConnection>,
+    ice_ufrag: &str,
+    ice_pwd: &str,
+) -> Result<String> {
+    let remote = peer
+        .remote_description()
+        .await
+        .ok_or(anyhow::anyhow!("failed to get remote description"))?;
+    let mut offer = String::new();
+    for line in remote.sdp.lines() {
+        if line.starts_with("a=ice-ufrag:") {
+            offer.push_str(&format!("a=ice-ufrag:{}\r\n", ice_ufrag));
+        } else if line.starts_with("a=ice-pwd:") {
+            offer.push_str(&format!("a=ice-pwd:{}\r\n", ice_pwd));
+        } else if !line.starts_with("a=candidate:") && !line.starts_with("a=end-of-candidates") {
+            offer.push_str(line);
+            offer.push_str("\r\n");
+        }
+    }
+    peer.set_remote_description(RTCSessionDescription::offer(offer)?)
+        .await?;
+    let answer = peer.create_answer(None).await?;
+    peer.set_local_description(answer).await?;
+
+    let answer = gathered_description(peer).await?;
+    let mut fragment = String::new();
+    for line in answer.sdp.lines() {
+        if line.starts_with("a=ice-ufrag:")
+            || line.starts_with("a=ice-pwd:")
+            || line.starts_with("m=")
+            || line.starts_with("a=mid:")
+            || line.starts_with("a=candidate:")
+            || line.starts_with("a=end-of-candidates")
+        {
+            fragment.push_str(line);
+            fragment.push_str("\r\n");
+        }
+    }
+    Ok(fragment)
+}
+
+/// Trickle ICE, or an ICE restart when the fragment has new ICE credentials.
+#[utoipa::path(
+    patch,
+    path = "/v2/stream/session/{room_id}/{session}/{secret}",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room of the session"),
+        ("session" = String, Path, description = "Session id"),
+        ("secret" = String, Path, description = "Secret of the session url")
+    ),
+    request_body(
+        content = String,
+        content_type = "application/trickle-ice-sdpfrag",
+        description = "Candidates, and new ICE credentials for an ICE restart"
+    ),
+    responses(
+        (status = 204, description = "Candidates added"),
+        (
+            status = 200,
+            description = "ICE restarted, with the server's new credentials and candidates",
+            content_type = "application/trickle-ice-sdpfrag",
+            body = String
+        )
+    )
+)]
+async fn patch_session(
+    Path((room_id, session, secret)): Path<(i32, String, String)>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response> {
+    debug!("HTTP PATCH /v2/stream/session");
+
+    check_secret(room_id, &session, &secret)?;
+    check_content_type(&headers, SDP_FRAGMENT)?;
+    let peer = session_peer(room_id, session).await?;
+    let fragment = SdpFragment::parse(&body);
+
+    let mut restart = None;
+    if let (Some(ice_ufrag), Some(ice_pwd)) = (&fragment.ice_ufrag, &fragment.ice_pwd) {
+        let remote = peer
+            .remote_description()
+            .await
+            .ok_or(anyhow::anyhow!("failed to get remote description"))?;
+        if self::ice_ufrag(&remote.sdp) != Some(ice_ufrag.as_str()) {
+            if peer.ice_gathering_state() == RTCIceGatheringState::Gathering {
+                return Err(AppError::conflict("ICE gathering is in progress"));
+            }
+            restart = Some(ice_restart(&peer, ice_ufrag, ice_pwd).await?);
+        }
+    }
+
+    for candidate in fragment.candidates {
+        debug!("session add ice-candidate: {}", candidate.candidate);
+        peer.add_ice_candidate(candidate)
+            .await
+            .map_err(AppError::invalid_request)?;
+    }
+
+    match restart {
+        Some(fragment) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, SDP_FRAGMENT)
+            .body(Body::from(fragment))?),
+        None => Ok(http::create_response(Body::empty(), StatusCode::NO_CONTENT)),
+    }
+}
+
+/// Closes the session, as `/v2/stream/close_session` does.
+#[utoipa::path(
+    delete,
+    path = "/v2/stream/session/{room_id}/{session}/{secret}",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room of the session"),
+        ("session" = String, Path, description = "Session id"),
+        ("secret" = String, Path, description = "Secret of the session url")
+    ),
+    responses((status = 204, description = "Closed"))
+)]
+async fn delete_session(
+    Path((room_id, session, secret)): Path<(i32, String, String)>,
+) -> Result<Response> {
+    debug!("HTTP DELETE /v2/stream/session");
+
+    check_secret(room_id, &session, &secret)?;
+    let room = find_room(room_id).await?;
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    let stream = forwarder.session_stream(session.clone()).await?;
+    forwarder.remove_session(stream, session).await?;
+
+    Ok(http::create_response(Body::empty(), StatusCode::NO_CONTENT))
+}
@@ -1,23 +1,28 @@
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::ConnectInfo;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::routing::post;
+use axum::Extension;
 use axum::Json;
 use axum::Router;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 
 use tracing::debug;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::auth::Permissions;
 use crate::constant;
 use crate::error::AppError;
 use crate::forward::rtc::message::Layer;
 use crate::http;
 use crate::result::Result;
+use crate::role::Permission;
 use crate::route::*;
+use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
     Router::new()
@@ -26,121 +31,152 @@ pub fn route() -> Router<AppState> {
         .merge(Router::new().route("/stream/get_layer/:base64/", post(get_layer)))
         .merge(Router::new().route("/stream/select_layer/:base64/", post(select_layer)))
         .merge(Router::new().route("/stream/un_select_layer/:base64/", post(un_select_layer)))
+        .merge(Router::new().route("/stream/close_session/:base64/", post(close_session)))
+        .route("/v2/stream/create", post(create))
+        .route("/v2/stream/destroy", post(destroy))
+        .route("/v2/stream/get_layer", post(get_layer))
+        .route("/v2/stream/select_layer", post(select_layer))
+        .route("/v2/stream/un_select_layer", post(un_select_layer))
+        .route("/v2/stream/close_session", post(close_session))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(
+    create,
+    destroy,
+    get_layer,
+    select_layer,
+    un_select_layer,
+    close_session
+))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
     stream: String,
+    #[serde(default)]
     shared_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct SelectLayerJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
     stream: String,
     session: String,
     layer: String,
+    #[serde(default)]
     shared_key: String,
 }
 
-async fn create(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
-    debug!("HTTP GET /stream/create");
+#[derive(Serialize, Deserialize, ToSchema)]
+struct SessionJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    stream: String,
+    /// `session` of the whip or whep answer.
+    session: String,
+    #[serde(default)]
+    shared_key: String,
+}
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+#[utoipa::path(
+    post,
+    path = "/v2/stream/create",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn create(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /stream/create");
 
-    let (room, client) = match auth_user(
+    let (room, client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
         request.token,
+        Some(Permission::CreateStream),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
-    let _ = match forwarder.stream_create(request.stream.clone()).await {
-        Ok(_) => {
-            let mut client = client;
-            client.add_stream(request.stream.clone()).await?;
-
-            Ok(Response::builder()
-                .status(StatusCode::NO_CONTENT)
-                .body("".to_string())?)
-        }
-        Err(e) => Err(AppError::StreamAlreadyExists(e.to_string())),
-    };
+    forwarder.stream_create(request.stream.clone()).await?;
+    let mut client = client;
+    client.add_stream(request.stream.clone()).await?;
 
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
 
-async fn destroy(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/stream/destroy",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn destroy(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
     debug!("HTTP GET /stream/destroy");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (room, client) = match auth_user(
+    let (room, client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
         request.token,
+        Some(Permission::CreateStream),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
-    let _ = match forwarder.stream_delete(request.stream.clone()).await {
-        Ok(_) => {
-            let mut client = client;
-            client.remove_stream(request.stream.clone()).await?;
-
-            Ok(Response::builder()
-                .status(StatusCode::NO_CONTENT)
-                .body("".to_string())?)
-        }
-        Err(e) => Err(AppError::StreamAlreadyExists(e.to_string())),
-    };
+    forwarder.stream_delete(request.stream.clone()).await?;
+    let mut client = client;
+    client.remove_stream(request.stream.clone()).await?;
 
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
 
-async fn get_layer(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/stream/get_layer",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(RequestJson),
+    responses((status = 200, description = "Simulcast layers of the stream", body = Vec<Layer>))
+)]
+async fn get_layer(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<RequestJson>,
+) -> Result<Response> {
     debug!("HTTP GET /stream/get_layer");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (room, _client) = match auth_user(
+    let (room, _client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
         request.token,
+        Some(Permission::Subscribe),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
@@ -154,25 +190,29 @@ async fn get_layer(Path(params): Path<HashMap<String, String>>) -> Result<Respon
     return Ok(Json(layers).into_response());
 }
 
-async fn select_layer(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/stream/select_layer",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(SelectLayerJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn select_layer(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<SelectLayerJson>,
+) -> Result<Response> {
     debug!("HTTP GET /stream/select_layer");
 
-    let request: SelectLayerJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (room, _client) = match auth_user(
+    let (room, _client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
         request.token,
+        Some(Permission::Subscribe),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
@@ -189,25 +229,29 @@ async fn select_layer(Path(params): Path<HashMap<String, String>>) -> Result<Res
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
 
-async fn un_select_layer(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+#[utoipa::path(
+    post,
+    path = "/v2/stream/un_select_layer",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(SelectLayerJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn un_select_layer(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestArgs(request): RequestArgs<SelectLayerJson>,
+) -> Result<Response> {
     debug!("HTTP GET /stream/un_select_layer");
 
-    let request: SelectLayerJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (room, _client) = match auth_user(
+    let (room, _client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
         request.token,
+        Some(Permission::Subscribe),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
@@ -223,3 +267,61 @@ async fn un_select_layer(Path(params): Path<HashMap<String, String>>) -> Result<
 
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
+
+/// Closes a publish or subscribe session of the stream. Only the user owning
+/// the session and the host may close it, or admins without credentials.
+#[utoipa::path(
+    post,
+    path = "/v2/stream/close_session",
+    tag = "stream",
+    params(CredentialHeaders),
+    request_body = inline(SessionJson),
+    responses((status = 200, description = "Succeed", content_type = "text/plain", body = String))
+)]
+async fn close_session(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(permissions): Extension<Permissions>,
+    RequestArgs(request): RequestArgs<SessionJson>,
+) -> Result<Response> {
+    debug!("HTTP GET /stream/close_session");
+
+    let room = match permissions.admin {
+        true => {
+            let rooms = ROOMS.lock().await;
+            match rooms.get(&request.room_id) {
+                Some(room) => room.clone(),
+                None => return Err(AppError::RoomNotFound),
+            }
+        }
+        false => {
+            let (room, _client) = auth_user(
+                request.room_id,
+                request.shared_key.clone(),
+                request.user_id,
+                request.token.clone(),
+                None,
+                addr.ip(),
+            )
+            .await?;
+            room
+        }
+    };
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    if !permissions.admin {
+        let owner = forwarder
+            .session_user(request.stream.clone(), request.session.clone())
+            .await?;
+        if owner != request.user_id as u32 && !room.is_host(request.user_id).await {
+            return Err(AppError::permission_denied(
+                "only the owner of the session or the host may close it",
+            ));
+        }
+    }
+    forwarder
+        .remove_session(request.stream.clone(), request.session.clone())
+        .await?;
+
+    return Ok(http::create_response(Body::from(""), StatusCode::OK));
+}
@@ -1,41 +1,78 @@
 use axum::extract::ws;
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::mpsc;
 use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
 use tracing::{debug, error};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
+use crate::forward::rtc::client::TokenDigest;
 use crate::result::Result;
+use crate::role::Permission;
 use crate::room::Room;
+use crate::route::rtc::session::session_url;
+use crate::route::rtc::{
+    created_response, gathered_description, receive_offer, sdp_offer, virtual_publish,
+};
 use crate::route::*;
+use crate::webhook::{WebhookAction, WebhookRequest};
 use crate::ROOMS;
 
+/// How long `POST` waits for the publisher of the stream to connect.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub fn route() -> Router<AppState> {
-    Router::new().route("/stream/whep/:base64/", get(whep))
+    Router::new()
+        .route("/stream/whep/:base64/", get(whep))
+        .route("/v2/stream/whep", get(whep).post(whep_http))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(whep, whep_http))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct RequestJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
     stream: String,
+    /// Sent as the first message instead when empty, for offers too large
+    /// for a url.
+    #[serde(default)]
     offer: String,
+    #[serde(default)]
     shared_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Arguments of `POST /v2/stream/whep`, whose body is the offer.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HttpRequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    stream: String,
+    #[serde(default)]
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct SignalingJson {
     is_candidate: bool,
     sdp: String,
@@ -43,134 +80,66 @@ struct SignalingJson {
     candidate: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v2/stream/whep",
+    tag = "stream",
+    params(CredentialHeaders, RequestJson),
+    responses((status = 101, description = "WebSocket carrying the SDP answer and ICE candidates"))
+)]
 async fn whep(
-    Path(params): Path<HashMap<String, String>>,
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
+    RequestArgs(request): RequestArgs<RequestJson>,
 ) -> Result<Response> {
     debug!("HTTP GET /stream/whep");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (room, _client) = match auth_user(
+    let (room, _client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
-        request.token,
+        request.token.clone(),
+        Some(Permission::Subscribe),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
+    .await?;
+
+    let webhook_request = WebhookRequest {
+        room_id: Some(request.room_id),
+        user_id: Some(request.user_id),
+        stream: Some(request.stream.clone()),
+        ..WebhookRequest::new(WebhookAction::Subscribe, addr.ip(), &headers)
     };
+    authorize(&state, webhook_request).await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
     if !forwarder.is_stream_exists(request.stream.clone()).await? {
-        let (tx0, mut rx) = mpsc::channel::<(u8, String)>(32);
-        let tx1 = tx0.clone();
-        let caches0: Arc<RwLock<Vec<String>>> = Default::default();
-        let caches1: Arc<RwLock<Vec<String>>> = Default::default();
-        let caches0c = Arc::clone(&caches0);
-        let caches1c = Arc::clone(&caches1);
-        let handle = tokio::spawn(async move {
-            let mut c1 = caches1c.write().await;
-            let mut c0 = caches0c.write().await;
-            while let Some(message) = rx.recv().await {
-                match message.0 {
-                    0 => {
-                        c1.push(message.1);
-                    }
-                    1 => {
-                        c0.push(message.1);
-                    }
-                    _ => todo!(),
-                };
-
-                if c0.len() > 0 && c1.len() > 0 {
-                    break;
-                }
-            }
-        });
-        let (peer0, sdp, _session) = forwarder
-            .virtual_publish(
-                request.stream.clone(),
-                Box::new(move |candidate: Option<RTCIceCandidate>| {
-                    let candidate = candidate.clone();
-                    let tx0 = tx0.clone();
-                    if let Some(candidate) = candidate {
-                        return Box::pin(async move {
-                            let c = candidate.to_json().unwrap().candidate;
-                            if let Err(_err) = tx0.clone().send((0, c.clone())).await {}
-                        });
-                    }
-                    Box::pin(async {})
-                }),
-            )
-            .await?;
-        let id = request.user_id as u32;
-        let (peer1, answer, _session) = forwarder
-            .publish(
-                request.stream.clone(),
-                id,
-                sdp,
-                Box::new(move |candidate: Option<RTCIceCandidate>| {
-                    let candidate = candidate.clone();
-                    let tx1 = tx1.clone();
-                    if let Some(candidate) = candidate {
-                        return Box::pin(async move {
-                            let c = candidate.to_json().unwrap().candidate;
-                            if let Err(_err) = tx1.clone().send((1, c.clone())).await {}
-                        });
-                    }
-                    Box::pin(async {})
-                }),
-                Box::new(move || Box::pin(async {})),
-            )
-            .await?;
-        peer0.set_remote_description(answer).await?;
-
-        while !handle.is_finished() {}
-
-        let caches0 = caches0.read().await;
-        let caches0 = caches0.iter();
-        for candidate in caches0 {
-            debug!("[vhost] peer0 add ice-candidate: {}", candidate);
-            if let Err(err) = peer0
-                .add_ice_candidate(RTCIceCandidateInit {
-                    candidate: candidate.to_string(),
-                    ..Default::default()
-                })
-                .await
-            {
-                error!("{}", err);
-            }
-        }
-
-        let caches1 = caches1.read().await;
-        let caches1 = caches1.iter();
-        for candidate in caches1 {
-            debug!("[vhost] peer1 add ice-candidate: {}", candidate);
-            if let Err(err) = peer1
-                .add_ice_candidate(RTCIceCandidateInit {
-                    candidate: candidate.to_string(),
-                    ..Default::default()
-                })
-                .await
-            {
-                error!("{}", err);
-            }
-        }
+        virtual_publish(
+            &forwarder,
+            request.stream.clone(),
+            request.user_id as u32,
+            TokenDigest::new(&request.token),
+        )
+        .await?;
     }
+    drop(forwarder);
 
     return Ok(ws.on_upgrade(|mut socket: WebSocket| {
         let request = request;
         Box::pin(async move {
             let stream = request.stream;
             let id = request.user_id as u32;
-            let offer = RTCSessionDescription::offer(request.offer).unwrap();
+            let offer = match request.offer.is_empty() {
+                true => match receive_offer(&mut socket).await {
+                    Some(offer) => offer,
+                    None => return,
+                },
+                false => request.offer,
+            };
+            let offer = RTCSessionDescription::offer(offer).unwrap();
 
             let mut rooms = ROOMS.lock().await;
             if !rooms.contains_key(&request.room_id) {
@@ -191,6 +160,7 @@ async fn whep(
                 .subscribe(
                     stream.clone(),
                     id.clone(),
+                    TokenDigest::new(&request.token),
                     offer.clone(),
                     Box::new(move |candidate: Option<RTCIceCandidate>| {
                         let candidate = candidate.clone();
@@ -296,3 +266,95 @@ async fn whep(
         })
     }));
 }
+
+/// WHEP, the subscribe side of RFC 9725. A stream that nobody publishes gets
+/// a publisher inside the server, as with `GET`.
+#[utoipa::path(
+    post,
+    path = "/v2/stream/whep",
+    tag = "stream",
+    params(CredentialHeaders, HttpRequestJson),
+    request_body(content = String, content_type = "application/sdp", description = "Offer"),
+    responses((
+        status = 201,
+        description = "Answer, with all of the server's ICE candidates",
+        content_type = "application/sdp",
+        body = String,
+        headers(
+            ("location" = String, description = "Session url"),
+            ("link" = String, description = "An ICE server, `rel=\"ice-server\"`")
+        )
+    ))
+)]
+async fn whep_http(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    QueryArgs(request): QueryArgs<HttpRequestJson>,
+    body: String,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/stream/whep");
+
+    let offer = sdp_offer(&headers, body)?;
+
+    let (room, _client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token.clone(),
+        Some(Permission::Subscribe),
+        addr.ip(),
+    )
+    .await?;
+
+    let webhook_request = WebhookRequest {
+        room_id: Some(request.room_id),
+        user_id: Some(request.user_id),
+        stream: Some(request.stream.clone()),
+        ..WebhookRequest::new(WebhookAction::Subscribe, addr.ip(), &headers)
+    };
+    authorize(&state, webhook_request).await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    if !forwarder.is_stream_exists(request.stream.clone()).await? {
+        virtual_publish(
+            &forwarder,
+            request.stream.clone(),
+            request.user_id as u32,
+            TokenDigest::new(&request.token),
+        )
+        .await?;
+    }
+
+    // Unlike the WebSocket, the request can not wait forever for the publisher
+    let _ = tokio::time::timeout(PUBLISH_TIMEOUT, async {
+        while !forwarder
+            .publish_is_ok(request.stream.clone())
+            .await
+            .unwrap_or(true)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+
+    let (peer, _answer, session) = forwarder
+        .subscribe(
+            request.stream.clone(),
+            request.user_id as u32,
+            TokenDigest::new(&request.token),
+            offer,
+            Box::new(move |_candidate: Option<RTCIceCandidate>| Box::pin(async {})),
+            Box::new(move || Box::pin(async {})),
+        )
+        .await?;
+    drop(forwarder);
+
+    let answer = gathered_description(&peer).await?;
+    created_response(
+        &state.config.ice_servers,
+        session_url(request.room_id, &session),
+        answer,
+    )
+}
@@ -1,40 +1,72 @@
 use axum::extract::ws;
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::net::SocketAddr;
 use tokio::sync::mpsc;
 use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
 use tracing::{debug, error};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
+use crate::forward::rtc::client::TokenDigest;
 use crate::result::Result;
+use crate::role::Permission;
 use crate::room::Room;
+use crate::route::rtc::session::session_url;
+use crate::route::rtc::{created_response, gathered_description, receive_offer, sdp_offer};
 use crate::route::*;
+use crate::webhook::{WebhookAction, WebhookRequest};
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/stream/whip/:base64/", get(whip))
+    Router::new()
+        .route("/stream/whip/:base64/", get(whip))
+        .route("/v2/stream/whip", get(whip).post(whip_http))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(whip, whip_http))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct RequestJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
     stream: String,
+    /// Sent as the first message instead when empty, for offers too large
+    /// for a url.
+    #[serde(default)]
     offer: String,
+    #[serde(default)]
+    shared_key: String,
+}
+
+/// Arguments of `POST /v2/stream/whip`, whose body is the offer.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HttpRequestJson {
+    room_id: i32,
+    user_id: i32,
+    #[serde(default)]
+    token: String,
+    stream: String,
+    #[serde(default)]
     shared_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct SignalingJson {
     is_candidate: bool,
     sdp: String,
@@ -42,35 +74,53 @@ struct SignalingJson {
     candidate: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v2/stream/whip",
+    tag = "stream",
+    params(CredentialHeaders, RequestJson),
+    responses((status = 101, description = "WebSocket carrying the SDP answer and ICE candidates"))
+)]
 async fn whip(
-    Path(params): Path<HashMap<String, String>>,
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
+    RequestArgs(request): RequestArgs<RequestJson>,
 ) -> Result<Response> {
     debug!("HTTP GET /stream/whip");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (_room, client) = match auth_user(
+    let (_room, client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
-        request.token,
+        request.token.clone(),
+        Some(Permission::Publish),
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
+    .await?;
+
+    let webhook_request = WebhookRequest {
+        room_id: Some(request.room_id),
+        user_id: Some(request.user_id),
+        stream: Some(request.stream.clone()),
+        ..WebhookRequest::new(WebhookAction::Publish, addr.ip(), &headers)
     };
+    authorize(&state, webhook_request).await?;
 
     return Ok(ws.on_upgrade(|mut socket: WebSocket| {
         let request = request;
         Box::pin(async move {
             let stream = request.stream;
             let id = request.user_id as u32;
-            let offer = RTCSessionDescription::offer(request.offer).unwrap();
+            let offer = match request.offer.is_empty() {
+                true => match receive_offer(&mut socket).await {
+                    Some(offer) => offer,
+                    None => return,
+                },
+                false => request.offer,
+            };
+            let offer = RTCSessionDescription::offer(offer).unwrap();
 
             let mut rooms = ROOMS.lock().await;
             if !rooms.contains_key(&request.room_id) {
@@ -89,6 +139,7 @@ async fn whip(
                 .publish(
                     stream.clone(),
                     id.clone(),
+                    TokenDigest::new(&request.token),
                     offer.clone(),
                     Box::new(move |candidate: Option<RTCIceCandidate>| {
                         let candidate = candidate.clone();
@@ -197,3 +248,76 @@ async fn whip(
         })
     }));
 }
+
+/// WHIP as in RFC 9725. The session url of `Location` takes trickled
+/// candidates, ICE restarts and the teardown.
+#[utoipa::path(
+    post,
+    path = "/v2/stream/whip",
+    tag = "stream",
+    params(CredentialHeaders, HttpRequestJson),
+    request_body(content = String, content_type = "application/sdp", description = "Offer"),
+    responses((
+        status = 201,
+        description = "Answer, with all of the server's ICE candidates",
+        content_type = "application/sdp",
+        body = String,
+        headers(
+            ("location" = String, description = "Session url"),
+            ("link" = String, description = "An ICE server, `rel=\"ice-server\"`")
+        )
+    ))
+)]
+async fn whip_http(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    QueryArgs(request): QueryArgs<HttpRequestJson>,
+    body: String,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/stream/whip");
+
+    let offer = sdp_offer(&headers, body)?;
+
+    let (room, client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token.clone(),
+        Some(Permission::Publish),
+        addr.ip(),
+    )
+    .await?;
+
+    let webhook_request = WebhookRequest {
+        room_id: Some(request.room_id),
+        user_id: Some(request.user_id),
+        stream: Some(request.stream.clone()),
+        ..WebhookRequest::new(WebhookAction::Publish, addr.ip(), &headers)
+    };
+    authorize(&state, webhook_request).await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    let (peer, _answer, session) = forwarder
+        .publish(
+            request.stream.clone(),
+            request.user_id as u32,
+            TokenDigest::new(&request.token),
+            offer,
+            Box::new(move |_candidate: Option<RTCIceCandidate>| Box::pin(async {})),
+            Box::new(move || Box::pin(async {})),
+        )
+        .await?;
+    drop(forwarder);
+
+    let mut client = client;
+    let _ = client.add_stream(request.stream.clone()).await;
+
+    let answer = gathered_description(&peer).await?;
+    created_response(
+        &state.config.ice_servers,
+        session_url(request.room_id, &session),
+        answer,
+    )
+}
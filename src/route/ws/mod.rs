@@ -1,54 +1,68 @@
+use std::net::SocketAddr;
 use std::usize;
 
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::ConnectInfo;
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
 use futures_util::{SinkExt, StreamExt};
+use libws::{event_message, EventType};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
+use utoipa::{IntoParams, OpenApi};
 
 use crate::result::Result;
+use crate::role::Permission;
 use crate::room::Room;
 use crate::route::*;
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/ws/connect/:base64/", get(stream))
+    Router::new()
+        .route("/ws/connect/:base64/", get(stream))
+        .route("/v2/ws/connect", get(stream))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(paths(stream))]
+pub struct Api;
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct RequestJson {
     room_id: i32,
     user_id: i32,
-    token: u32,
+    #[serde(default)]
+    token: String,
     stream: String,
+    #[serde(default)]
     shared_key: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v2/ws/connect",
+    tag = "ws",
+    params(CredentialHeaders, RequestJson),
+    responses((status = 101, description = "WebSocket carrying the binary messages of the room"))
+)]
 async fn stream(
-    Path(params): Path<HashMap<String, String>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     ws: WebSocketUpgrade,
+    RequestArgs(request): RequestArgs<RequestJson>,
 ) -> Result<Response> {
     debug!("HTTP GET /ws/connect");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
-
-    let (_room, _client) = match auth_user(
+    let (_room, _client) = auth_user(
         request.room_id.clone(),
         request.shared_key.clone(),
         request.user_id,
-        request.token,
+        request.token.clone(),
+        None,
+        addr.ip(),
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     return Ok(ws.on_upgrade(|socket: WebSocket| {
         let request = request;
@@ -64,6 +78,10 @@ async fn stream(
             let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
 
             let (mut socekt_sender, mut socket_receiver) = socket.split();
+            let properties_message = room.properties_full_message().await;
+            let is_resumed = room.ws_attach(&stream, id, &request.token).await;
+            let notice_open = !is_resumed && room.roles().read().await.notices_events(id as i32);
+            let roles = room.roles();
             let group_manager = room.group_manager();
             let group_manager = group_manager.write().await;
 
@@ -103,6 +121,14 @@ async fn stream(
             };
             drop(group_manager);
 
+            // Late joiners receive the whole property set before anything else
+            if let Err(err) = socekt_sender
+                .send(Message::Binary(properties_message))
+                .await
+            {
+                info!("[ws] send socket err: {}", err);
+            }
+
             debug!("[ws] start receive/send loop ...");
 
             let mut send_task = tokio::spawn(async move {
@@ -122,17 +148,15 @@ async fn stream(
                     header[i + 1] = (id >> (i * 8)) as u8;
                 }
 
-                header[0] = 1; // open
-                let mut dummy_buf = vec![0u8; 4];
-                for i in 0..4 {
-                    dummy_buf[i] = (id >> (i * 8)) as u8;
-                }
-                if let Err(err) = group_sender.send([header.clone(), dummy_buf.clone()].concat()) {
-                    info!("[ws] send socket err: {}", err);
-                    return;
+                // A resumed connection was never announced as closed
+                if notice_open {
+                    if let Err(err) = group_sender.send(event_message(EventType::Open, id, id)) {
+                        info!("[ws] send socket err: {}", err);
+                        return;
+                    }
                 }
 
-                header[0] = 0; // struct
+                header[0] = EventType::Struct as u8;
 
                 while let Some(Ok(message)) = socket_receiver.next().await {
                     match message {
@@ -142,6 +166,18 @@ async fn stream(
                         Message::Binary(binary) => {
                             //debug!("[ws] received binary message: {:?}", &binary);
                             let is_broadcast = header[1..5] == binary[..4];
+                            let permission = if is_broadcast {
+                                Permission::Send
+                            } else {
+                                Permission::Unicast
+                            };
+                            if !roles.read().await.allows(id as i32, permission) {
+                                debug!(
+                                    "[ws] user : {} may not {:?}, message dropped",
+                                    id, permission
+                                );
+                                continue;
+                            }
                             if is_broadcast {
                                 //debug!("[ws] send broadcast message");
                                 if let Err(err) = 
@@ -186,12 +222,13 @@ async fn stream(
                 _ = (&mut recv_task) => send_task.abort(),
             };
 
-            let mut rooms = ROOMS.lock().await;
-            let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
-            let group_manager = room.group_manager();
-            let group_manager = group_manager.write().await;
-            let _ = group_manager.leave_group(stream.clone(), id).await;
-            drop(group_manager);
+            let rooms = ROOMS.lock().await;
+            let room = rooms.get(&request.room_id).cloned();
+            drop(rooms);
+
+            if let Some(room) = room {
+                room.ws_detach(stream.clone(), id, request.token.clone()).await;
+            }
 
             info!("[ws] connection closed");
         })
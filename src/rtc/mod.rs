@@ -10,13 +10,18 @@ use webrtc::peer_connection::RTCPeerConnection;
 
 use crate::config::Config;
 use crate::error::AppError;
+use crate::event::{RoomEvent, RoomEvents};
+use crate::forward::rtc::client::TokenDigest;
 use crate::forward::rtc::message::{ForwardInfo, Layer};
 use crate::forward::rtc::{OnPeerConnectionEvtHdlrFn, PeerForward};
+use crate::property::RoomProperties;
 use crate::result::Result;
+use crate::role::RoomRoles;
 
 use chrono::{DateTime, Utc};
 
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 use tracing::{debug, info};
 
 pub mod convert;
@@ -24,12 +29,17 @@ pub mod convert;
 pub struct Forwarder {
     stream_map: Arc<RwLock<HashMap<String, PeerForward>>>,
     config: ForwarderConfig,
+    properties: Arc<RwLock<RoomProperties>>,
+    roles: Arc<RwLock<RoomRoles>>,
+    events: Arc<RoomEvents>,
+    publish_check: JoinHandle<()>,
 }
 
 pub struct ForwarderConfig {
     pub ice_servers: Vec<RTCIceServer>,
     pub reforward_close_sub: bool,
     pub publish_leave_timeout: u64,
+    pub resume_grace: u64,
 }
 
 impl ForwarderConfig {
@@ -44,21 +54,32 @@ impl ForwarderConfig {
             ice_servers,
             reforward_close_sub: cfg.stream_info.reforward_close_sub,
             publish_leave_timeout: cfg.stream_info.publish_leave_timeout.0,
+            resume_grace: cfg.room.resume_grace,
         }
     }
 }
 
 impl Forwarder {
-    pub fn new(cfg: ForwarderConfig) -> Self {
+    pub fn new(
+        cfg: ForwarderConfig,
+        properties: Arc<RwLock<RoomProperties>>,
+        roles: Arc<RwLock<RoomRoles>>,
+        events: Arc<RoomEvents>,
+    ) -> Self {
         let stream_map: Arc<RwLock<HashMap<String, PeerForward>>> = Default::default();
-        tokio::spawn(Self::publish_check_tick(
+        let publish_check = tokio::spawn(Self::publish_check_tick(
             stream_map.clone(),
             cfg.publish_leave_timeout,
+            events.clone(),
         ));
 
         let live: Forwarder = Self {
             stream_map: stream_map,
             config: cfg,
+            properties: properties,
+            roles: roles,
+            events: events,
+            publish_check: publish_check,
         };
 
         live
@@ -67,6 +88,7 @@ impl Forwarder {
     async fn publish_check_tick(
         stream_map: Arc<RwLock<HashMap<String, PeerForward>>>,
         publish_leave_timeout: u64,
+        events: Arc<RoomEvents>,
     ) {
         let publish_leave_timeout_i64: i64 = publish_leave_timeout.try_into().unwrap();
         loop {
@@ -96,6 +118,9 @@ impl Forwarder {
                     {
                         let _ = forward.close().await;
                         stream_map.remove(stream);
+                        events.send(RoomEvent::StreamDestroy {
+                            stream: stream.clone(),
+                        });
                         let publish_leave_time =
                             DateTime::from_timestamp_millis(forward_info.publish_leave_time)
                                 .unwrap()
@@ -111,38 +136,58 @@ impl Forwarder {
         }
     }
 
-    pub async fn stream_create(&self, stream: String) -> std::result::Result<(), anyhow::Error> {
+    pub async fn stream_create(&self, stream: String) -> Result<()> {
         let mut stream_map = self.stream_map.write().await;
         let forward = stream_map.get(&stream).cloned();
         if forward.is_some() {
-            return Err(anyhow::anyhow!("stream already exists"));
+            return Err(AppError::stream_already_exists("stream already exists"));
         }
         debug!("create stream: {}", stream.clone());
         let forward = self.do_stream_create(stream.clone()).await;
-        stream_map.insert(stream.clone(), forward);
-        drop(stream_map);
+        forward.announce();
+        stream_map.insert(stream, forward);
         Ok(())
     }
 
     async fn do_stream_create(&self, stream: String) -> PeerForward {
-        let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
+        let forward = PeerForward::new(
+            stream.clone(),
+            self.config.ice_servers.clone(),
+            self.properties.clone(),
+            self.roles.clone(),
+            self.config.resume_grace,
+            self.events.clone(),
+        );
         forward
     }
 
-    pub async fn stream_delete(&self, stream: String) -> std::result::Result<(), anyhow::Error> {
+    pub async fn stream_delete(&self, stream: String) -> Result<()> {
         let mut stream_map = self.stream_map.write().await;
         let forward = stream_map.get(&stream).cloned();
         let _ = match forward {
             Some(forward) => forward.close().await,
-            None => return Err(anyhow::anyhow!("stream not exists")),
+            None => return Err(AppError::stream_not_found("stream not exists")),
         };
         stream_map.remove(&stream);
         drop(stream_map);
 
         info!("remove stream : {}", stream);
+        self.events.send(RoomEvent::StreamDestroy { stream });
         Ok(())
     }
 
+    /// Closes every stream and stops the publish check. The forwarder must
+    /// not be used afterwards.
+    pub async fn close(&self) {
+        self.publish_check.abort();
+        let mut stream_map = self.stream_map.write().await;
+        for (stream, forward) in stream_map.drain() {
+            let _ = forward.close().await;
+            info!("remove stream : {}", stream);
+            self.events.send(RoomEvent::StreamDestroy { stream });
+        }
+    }
+
     pub async fn is_stream_exists(&self, stream: String) -> Result<bool> {
         let stream_map = self.stream_map.read().await;
         return Ok(stream_map.contains_key(&stream));
@@ -186,7 +231,14 @@ impl Forwarder {
         if let Some(forward) = forward {
             forward.gen_virtual_publish(on_ice_candidate).await
         } else {
-            let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
+            let forward = PeerForward::new(
+                stream.clone(),
+                self.config.ice_servers.clone(),
+                self.properties.clone(),
+                self.roles.clone(),
+                self.config.resume_grace,
+                self.events.clone(),
+            );
             let (peer, sdp, session) = forward.gen_virtual_publish(on_ice_candidate).await?;
             let mut stream_map = self.stream_map.write().await;
             if stream_map.contains_key(&stream) {
@@ -194,6 +246,8 @@ impl Forwarder {
                 return Err(AppError::stream_already_exists("stream already exists"));
             }
             info!("add stream : {}", stream);
+            // Announced under the lock so that a delete can not come first
+            forward.announce();
             stream_map.insert(stream.clone(), forward);
             Ok((peer, sdp, session))
         }
@@ -203,6 +257,7 @@ impl Forwarder {
         &self,
         stream: String,
         id: u32,
+        token: TokenDigest,
         offer: RTCSessionDescription,
         on_ice_candidate: OnLocalCandidateHdlrFn,
         on_peer_connected: OnPeerConnectionEvtHdlrFn,
@@ -212,12 +267,19 @@ impl Forwarder {
         drop(stream_map);
         if let Some(forward) = forward {
             forward
-                .set_publish(id, offer, on_ice_candidate, on_peer_connected)
+                .set_publish(id, token, offer, on_ice_candidate, on_peer_connected)
                 .await
         } else {
-            let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
+            let forward = PeerForward::new(
+                stream.clone(),
+                self.config.ice_servers.clone(),
+                self.properties.clone(),
+                self.roles.clone(),
+                self.config.resume_grace,
+                self.events.clone(),
+            );
             let (peer, sdp, session) = forward
-                .set_publish(id, offer, on_ice_candidate, on_peer_connected)
+                .set_publish(id, token, offer, on_ice_candidate, on_peer_connected)
                 .await?;
             let mut stream_map = self.stream_map.write().await;
             if stream_map.contains_key(&stream) {
@@ -225,6 +287,8 @@ impl Forwarder {
                 return Err(AppError::stream_already_exists("stream already exists"));
             }
             info!("add stream : {}", stream);
+            // Announced under the lock so that a delete can not come first
+            forward.announce();
             stream_map.insert(stream.clone(), forward);
             Ok((peer, sdp, session))
         }
@@ -245,6 +309,7 @@ impl Forwarder {
         &self,
         stream: String,
         id: u32,
+        token: TokenDigest,
         offer: RTCSessionDescription,
         on_ice_candidate: OnLocalCandidateHdlrFn,
         on_peer_connected: OnPeerConnectionEvtHdlrFn,
@@ -254,7 +319,7 @@ impl Forwarder {
         drop(stream_map);
         if let Some(forward) = forward {
             let (peer, sdp, session) = forward
-                .add_subscribe(id, offer, on_ice_candidate, on_peer_connected)
+                .add_subscribe(id, token, offer, on_ice_candidate, on_peer_connected)
                 .await?;
             Ok((peer, sdp, session))
         } else {
@@ -262,6 +327,83 @@ impl Forwarder {
         }
     }
 
+    /// The peer of a publish or subscribe session, in whichever stream it is.
+    pub async fn session_peer(&self, session: String) -> Result<Arc<RTCPeerConnection>> {
+        let stream_map = self.stream_map.read().await;
+        for forward in stream_map.values() {
+            if let Some(peer) = forward.get_peer(session.clone()).await {
+                return Ok(peer);
+            }
+        }
+        Err(AppError::SessionNotFound)
+    }
+
+    /// The stream a publish or subscribe session belongs to.
+    pub async fn session_stream(&self, session: String) -> Result<String> {
+        let stream_map = self.stream_map.read().await;
+        for (stream, forward) in stream_map.iter() {
+            if forward.session_user(session.clone()).await.is_some() {
+                return Ok(stream.clone());
+            }
+        }
+        Err(AppError::SessionNotFound)
+    }
+
+    pub async fn session_user(&self, stream: String, session: String) -> Result<u32> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        match forward {
+            Some(forward) => forward
+                .session_user(session)
+                .await
+                .ok_or(AppError::SessionNotFound),
+            None => Err(AppError::stream_not_found("stream not exists")),
+        }
+    }
+
+    pub async fn remove_session(&self, stream: String, session: String) -> Result<()> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.remove_session(session.clone()).await?;
+            info!("stream : {}, remove session : {}", stream, session);
+            Ok(())
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    /// Closes every subscribe session of the user, in all streams.
+    pub async fn close_user_subscribes(&self, id: u32) {
+        let stream_map = self.stream_map.read().await;
+        for (stream, forward) in stream_map.iter() {
+            if let Err(err) = forward.close_user_subscribes(id).await {
+                debug!(
+                    "stream : {}, close subscribe of {} err: {:?}",
+                    stream, id, err
+                );
+            }
+        }
+    }
+
+    /// Drops whatever of the user could still be resumed on any stream.
+    pub async fn end_user_resume(&self, id: u32) {
+        let stream_map = self.stream_map.read().await;
+        for forward in stream_map.values() {
+            forward.end_user_resume(id).await;
+        }
+    }
+
+    /// Writes the message to every data channel of every stream.
+    pub async fn send_message_to_all(&self, message: Vec<u8>) {
+        let stream_map = self.stream_map.read().await;
+        for forward in stream_map.values() {
+            forward.send_message_to_all(message.clone()).await;
+        }
+    }
+
     pub async fn forward_infos(&self, streams: Vec<String>) -> Vec<ForwardInfo> {
         let mut streams = streams.clone();
         streams.retain(|stream| !stream.trim().is_empty());
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::{RoomSnapshot, RoomStore};
+
+/// Stores every snapshot in a single JSON file. The whole file is rewritten
+/// on each change, through a temporary file so a crash never leaves it half
+/// written.
+pub struct JsonRoomStore {
+    path: PathBuf,
+    rooms: Mutex<HashMap<i32, RoomSnapshot>>,
+}
+
+impl JsonRoomStore {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let path = PathBuf::from(path);
+        let mut rooms = HashMap::new();
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            if !content.trim().is_empty() {
+                let snapshots: Vec<RoomSnapshot> = serde_json::from_str(&content)?;
+                for snapshot in snapshots {
+                    rooms.insert(snapshot.id, snapshot);
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            rooms: Mutex::new(rooms),
+        })
+    }
+
+    fn flush(&self, rooms: &HashMap<i32, RoomSnapshot>) -> anyhow::Result<()> {
+        let snapshots: Vec<&RoomSnapshot> = rooms.values().collect();
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&snapshots)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl RoomStore for JsonRoomStore {
+    fn load(&self) -> anyhow::Result<Vec<RoomSnapshot>> {
+        let rooms = self.rooms.lock().unwrap();
+        Ok(rooms.values().cloned().collect())
+    }
+
+    fn save(&self, room: &RoomSnapshot) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.insert(room.id, room.clone());
+        self.flush(&rooms)
+    }
+
+    fn remove(&self, id: i32) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.remove(&id).is_some() {
+            self.flush(&rooms)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::{RoomSnapshot, RoomStore};
+
+/// Keeps snapshots for the lifetime of the process only. This is the default
+/// and matches the behaviour of a server without persistence.
+#[derive(Default)]
+pub struct MemoryRoomStore {
+    rooms: Mutex<HashMap<i32, RoomSnapshot>>,
+}
+
+impl MemoryRoomStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RoomStore for MemoryRoomStore {
+    fn load(&self) -> anyhow::Result<Vec<RoomSnapshot>> {
+        let rooms = self.rooms.lock().unwrap();
+        Ok(rooms.values().cloned().collect())
+    }
+
+    fn save(&self, room: &RoomSnapshot) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.insert(room.id, room.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: i32) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.remove(&id);
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::config;
+use crate::key::KeyHash;
+use crate::property::Property;
+use crate::role::RolePermissions;
+use crate::room::HostMigrationPolicy;
+
+pub mod json;
+pub mod memory;
+pub mod sqlite;
+
+/// Metadata of a room that survives a server restart. Clients, streams and
+/// ws groups are runtime state and are not part of the snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub id: i32,
+    pub name: String,
+    pub capacity: u32,
+    #[serde(default)]
+    pub spectator_capacity: u32,
+    pub needs_host: bool,
+    #[serde(default)]
+    pub host_migration: HostMigrationPolicy,
+    pub is_public: bool,
+    pub shared_key_hash: KeyHash,
+    pub master_key_hash: KeyHash,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub create_time: i64,
+    #[serde(default)]
+    pub properties: HashMap<String, Property>,
+    #[serde(default)]
+    pub invite_code: Option<String>,
+    /// Custom roles only, the built-in ones are not stored.
+    #[serde(default)]
+    pub roles: HashMap<String, RolePermissions>,
+    #[serde(default)]
+    pub default_role: Option<String>,
+    #[serde(default)]
+    pub spectator_events: bool,
+}
+
+pub trait RoomStore: Send + Sync {
+    /// Returns every room that has been saved and not removed yet.
+    fn load(&self) -> anyhow::Result<Vec<RoomSnapshot>>;
+
+    /// Inserts the room, or replaces the snapshot with the same id.
+    fn save(&self, room: &RoomSnapshot) -> anyhow::Result<()>;
+
+    fn remove(&self, id: i32) -> anyhow::Result<()>;
+}
+
+enum StoreOp {
+    Save(Box<RoomSnapshot>),
+    Remove(i32),
+}
+
+/// Hands changes to a background task that writes them one after another in
+/// the order they were queued. Queueing never waits, so it can be done while
+/// ROOMS is locked, which keeps the stored order the same as in memory, and
+/// no request waits on the disk. Write errors are only logged.
+#[derive(Clone)]
+pub struct StoreWriter {
+    sender: mpsc::UnboundedSender<StoreOp>,
+}
+
+impl StoreWriter {
+    pub fn spawn(store: Arc<dyn RoomStore>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(op) = receiver.recv().await {
+                let store = store.clone();
+                let result = tokio::task::spawn_blocking(move || match op {
+                    StoreOp::Save(room) => store.save(&room),
+                    StoreOp::Remove(id) => store.remove(id),
+                })
+                .await;
+                match result {
+                    Ok(Err(err)) => warn!("room store write err: {}", err),
+                    Err(err) => warn!("room store task err: {}", err),
+                    Ok(Ok(())) => {}
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    pub fn save(&self, room: RoomSnapshot) {
+        let _ = self.sender.send(StoreOp::Save(Box::new(room)));
+    }
+
+    pub fn remove(&self, id: i32) {
+        let _ = self.sender.send(StoreOp::Remove(id));
+    }
+}
+
+pub fn from_config(cfg: &config::Room) -> anyhow::Result<Arc<dyn RoomStore>> {
+    let path = cfg.store_path();
+    let store: Arc<dyn RoomStore> = match cfg.store {
+        config::RoomStoreKind::Memory => Arc::new(memory::MemoryRoomStore::new()),
+        config::RoomStoreKind::Json => Arc::new(json::JsonRoomStore::open(&path)?),
+        config::RoomStoreKind::Sqlite => Arc::new(sqlite::SqliteRoomStore::open(&path)?),
+    };
+    Ok(store)
+}
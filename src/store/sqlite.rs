@@ -0,0 +1,55 @@
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection};
+
+use super::{RoomSnapshot, RoomStore};
+
+/// Stores one row per room. The snapshot itself is kept as JSON so new room
+/// metadata does not require a schema migration.
+pub struct SqliteRoomStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteRoomStore {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS rooms (
+                id INTEGER PRIMARY KEY,
+                snapshot TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl RoomStore for SqliteRoomStore {
+    fn load(&self) -> anyhow::Result<Vec<RoomSnapshot>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT snapshot FROM rooms")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut snapshots = vec![];
+        for row in rows {
+            snapshots.push(serde_json::from_str(&row?)?);
+        }
+        Ok(snapshots)
+    }
+
+    fn save(&self, room: &RoomSnapshot) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO rooms (id, snapshot) VALUES (?1, ?2)",
+            params![room.id, serde_json::to_string(room)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, id: i32) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM rooms WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+}
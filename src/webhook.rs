@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use http::{header, HeaderMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::config;
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookAction {
+    Create,
+    Join,
+    Publish,
+    Subscribe,
+}
+
+/// Body POSTed to the account service. Fields that do not apply to the
+/// action are left out.
+#[derive(Debug, Serialize)]
+pub struct WebhookRequest {
+    pub action: WebhookAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invite_code: Option<String>,
+    pub remote_ip: IpAddr,
+    /// `Authorization` header of the client, so the service can tell who
+    /// is asking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<String>,
+}
+
+impl WebhookRequest {
+    pub fn new(action: WebhookAction, remote_ip: IpAddr, headers: &HeaderMap) -> Self {
+        Self {
+            action,
+            room_id: None,
+            user_id: None,
+            name: None,
+            stream: None,
+            invite_code: None,
+            remote_ip,
+            authorization: headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+        }
+    }
+}
+
+/// Answer of the account service. `role` and `metadata` are applied to the
+/// user of a join; metadata keys become the user's properties.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WebhookDecision {
+    pub allow: bool,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, Option<Value>>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl WebhookDecision {
+    fn allow() -> Self {
+        Self {
+            allow: true,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct AuthWebhook {
+    client: reqwest::Client,
+    config: config::Webhook,
+    /// Decisions by request body, with the time they expire.
+    cache: Mutex<HashMap<String, (WebhookDecision, i64)>>,
+}
+
+impl AuthWebhook {
+    pub fn new(config: config::Webhook) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout.0))
+            .build()
+            .expect("webhook client error");
+        Self {
+            client,
+            config,
+            cache: Default::default(),
+        }
+    }
+
+    /// Asks the account service, or answers from the cache. When the service
+    /// can not be reached or answers with garbage, `fail_open` decides
+    /// between allowing and failing with 503.
+    pub async fn authorize(&self, request: &WebhookRequest) -> Result<WebhookDecision, AppError> {
+        let body = serde_json::to_string(request).unwrap();
+        let now = Utc::now().timestamp_millis();
+
+        if self.config.cache_ttl > 0 {
+            let cache = self.cache.lock().unwrap();
+            if let Some((decision, expires_at)) = cache.get(&body) {
+                if *expires_at > now {
+                    return Ok(decision.clone());
+                }
+            }
+        }
+
+        let decision = match self.call(body.clone()).await {
+            Ok(decision) => decision,
+            Err(err) => {
+                warn!("auth webhook err: {}", err);
+                if self.config.fail_open {
+                    return Ok(WebhookDecision::allow());
+                }
+                return Err(AppError::ServiceUnavailable(
+                    "auth webhook unavailable".to_string(),
+                ));
+            }
+        };
+        if !decision.allow {
+            info!(
+                "auth webhook denied {:?} : {}",
+                request.action,
+                decision.reason.as_deref().unwrap_or("")
+            );
+        }
+
+        if self.config.cache_ttl > 0 {
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, (_, expires_at)| *expires_at > now);
+            cache.insert(body, (decision.clone(), now + self.config.cache_ttl as i64));
+        }
+        Ok(decision)
+    }
+
+    async fn call(&self, body: String) -> anyhow::Result<WebhookDecision> {
+        let mut builder = self
+            .client
+            .post(&self.config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(authorization) = &self.config.authorization {
+            builder = builder.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        let response = builder.send().await?;
+        let status = response.status();
+        // A plain 401 or 403 is a deny even without a body
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Ok(WebhookDecision::default());
+        }
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("status {}", status));
+        }
+        let bytes = response.bytes().await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Stands in for the account service. Callouts to `/allow` are counted.
+    async fn stub_service() -> (SocketAddr, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/allow",
+                post(|State(calls): State<Arc<AtomicUsize>>| async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({
+                        "allow": true,
+                        "role": "moderator",
+                        "metadata": { "level": 3, "team": null }
+                    }))
+                }),
+            )
+            .route(
+                "/deny",
+                post(|| async { Json(json!({ "allow": false, "reason": "banned" })) }),
+            )
+            .route("/forbidden", post(|| async { StatusCode::FORBIDDEN }))
+            .route(
+                "/slow",
+                post(|| async {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    Json(json!({ "allow": true }))
+                }),
+            )
+            .with_state(calls.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (addr, calls)
+    }
+
+    fn webhook(addr: SocketAddr, path: &str, cache_ttl: u64, fail_open: bool) -> AuthWebhook {
+        AuthWebhook::new(config::Webhook {
+            url: format!("http://{}{}", addr, path),
+            authorization: None,
+            timeout: config::WebhookTimeout(100),
+            cache_ttl,
+            fail_open,
+        })
+    }
+
+    fn join_request(room_id: i32) -> WebhookRequest {
+        WebhookRequest {
+            room_id: Some(room_id),
+            name: Some("player".to_string()),
+            ..WebhookRequest::new(
+                WebhookAction::Join,
+                "127.0.0.1".parse().unwrap(),
+                &HeaderMap::new(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn allow_with_role_and_metadata() {
+        let (addr, _) = stub_service().await;
+        let decision = webhook(addr, "/allow", 0, false)
+            .authorize(&join_request(1))
+            .await
+            .unwrap();
+        assert!(decision.allow);
+        assert_eq!(decision.role.as_deref(), Some("moderator"));
+        assert_eq!(decision.metadata.get("level"), Some(&Some(json!(3))));
+        assert_eq!(decision.metadata.get("team"), Some(&None));
+    }
+
+    #[tokio::test]
+    async fn deny() {
+        let (addr, _) = stub_service().await;
+        let decision = webhook(addr, "/deny", 0, false)
+            .authorize(&join_request(1))
+            .await
+            .unwrap();
+        assert!(!decision.allow);
+        assert_eq!(decision.reason.as_deref(), Some("banned"));
+
+        // fail_open does not turn a deny into an allow
+        let decision = webhook(addr, "/forbidden", 0, true)
+            .authorize(&join_request(1))
+            .await
+            .unwrap();
+        assert!(!decision.allow);
+    }
+
+    #[tokio::test]
+    async fn timeout_fail_open() {
+        let (addr, _) = stub_service().await;
+        let decision = webhook(addr, "/slow", 0, true)
+            .authorize(&join_request(1))
+            .await
+            .unwrap();
+        assert!(decision.allow);
+    }
+
+    #[tokio::test]
+    async fn timeout_fail_closed() {
+        let (addr, _) = stub_service().await;
+        let err = webhook(addr, "/slow", 0, false)
+            .authorize(&join_request(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn cache_ttl_reuses_decisions() {
+        let (addr, calls) = stub_service().await;
+        let webhook = webhook(addr, "/allow", 60_000, false);
+        webhook.authorize(&join_request(1)).await.unwrap();
+        webhook.authorize(&join_request(1)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Another request is a cache miss
+        webhook.authorize(&join_request(2)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Without a ttl every request is a callout
+        let (addr, calls) = stub_service().await;
+        let webhook = self::webhook(addr, "/allow", 0, false);
+        webhook.authorize(&join_request(1)).await.unwrap();
+        webhook.authorize(&join_request(1)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}